@@ -0,0 +1,46 @@
+use std::env;
+use std::fs;
+use std::path::Path;
+
+// Generates one #[test] per file in src/tests/cases and src/tests/snapshots,
+// included by src/tests/mod.rs
+fn main() {
+    let manifest_dir = env::var("CARGO_MANIFEST_DIR").unwrap();
+    let out_dir = env::var("OUT_DIR").unwrap();
+
+    for (dir, prefix, runner) in [
+        ("cases", "case", "run_case"),
+        ("snapshots", "snapshot", "check_snapshot"),
+    ] {
+        let dir_path = Path::new(&manifest_dir).join("src/tests").join(dir);
+        println!("cargo:rerun-if-changed={}", dir_path.display());
+
+        let mut tests = String::new();
+        for name in jlox_files(&dir_path) {
+            let ident: String = name
+                .trim_end_matches(".jlox")
+                .chars()
+                .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+                .collect();
+            tests.push_str(&format!(
+                "#[test]\nfn {}_{}() {{\n    {}(\"{}\");\n}}\n\n",
+                prefix, ident, runner, name
+            ));
+        }
+
+        let out = Path::new(&out_dir).join(format!("{}.rs", dir));
+        fs::write(out, tests).expect("Could not write generated tests");
+    }
+}
+
+fn jlox_files(dir: &Path) -> Vec<String> {
+    let mut names = vec![];
+    for entry in fs::read_dir(dir).unwrap_or_else(|e| panic!("Could not read {}: {}", dir.display(), e)) {
+        let path = entry.expect("Could not read test file").path();
+        if path.extension().is_some_and(|extension| extension == "jlox") {
+            names.push(path.file_name().unwrap().to_string_lossy().to_string());
+        }
+    }
+    names.sort();
+    names
+}
@@ -0,0 +1,964 @@
+use crate::expr::{Expr, LiteralValue, Parameter};
+use crate::generator::contains_yield;
+use crate::scanner::{Token, TokenType};
+use crate::stmt::{Field, Stmt};
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::rc::Rc;
+
+// What the checker knows about a value. Everything that is not annotated and can not
+// be inferred is `Any`, which is compatible with every other type.
+#[derive(Clone, PartialEq)]
+enum Type {
+    Any,
+    Nil,
+    Number,
+    String,
+    Boolean,
+    List,
+    Tuple,
+    Range,
+    Generator,
+    Task,
+    // Functions without a known signature
+    Callable,
+    Function(Rc<Signature>),
+    // A class itself, calling it makes an instance
+    Class(String),
+    // An instance of a class or trait, or a value of an enum
+    Named(String),
+}
+
+impl fmt::Display for Type {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Type::Any => write!(f, "Any"),
+            Type::Nil => write!(f, "nil"),
+            Type::Number => write!(f, "Number"),
+            Type::String => write!(f, "String"),
+            Type::Boolean => write!(f, "Boolean"),
+            Type::List => write!(f, "List"),
+            Type::Tuple => write!(f, "Tuple"),
+            Type::Range => write!(f, "Range"),
+            Type::Generator => write!(f, "Generator"),
+            Type::Task => write!(f, "Task"),
+            Type::Callable | Type::Function(_) => write!(f, "Callable"),
+            Type::Class(_) => write!(f, "Class"),
+            Type::Named(name) => write!(f, "{name}"),
+        }
+    }
+}
+
+#[derive(PartialEq)]
+struct Signature {
+    name: String,
+    // Positional parameters, `Any` where they are not annotated
+    params: Vec<(String, Type)>,
+    // For async functions the type of the value their task ends with
+    returns: Type,
+    is_async: bool,
+}
+
+// The type of an expression, and whether it follows from an annotation. Operators
+// are only checked on annotated types, so unannotated code runs exactly as before.
+#[derive(Clone)]
+struct Typed {
+    ty: Type,
+    declared: bool,
+}
+
+impl Typed {
+    fn inferred(ty: Type) -> Self {
+        Self {
+            ty,
+            declared: false,
+        }
+    }
+
+    fn any() -> Self {
+        Self::inferred(Type::Any)
+    }
+}
+
+struct ClassInfo {
+    superclass: Option<String>,
+    traits: Vec<String>,
+    fields: HashMap<String, Type>,
+    methods: HashMap<String, Rc<Signature>>,
+}
+
+// Checks annotated types between resolving and running a program. Unannotated
+// locals get the type of their initializer, unless they are assigned to later.
+pub struct Checker {
+    scopes: Vec<HashMap<String, Typed>>,
+    classes: HashMap<String, ClassInfo>,
+    // Enums and traits, which can be used as types too
+    type_names: HashSet<String>,
+    // Variables assigned to after their declaration, these keep the type `Any`
+    reassigned: HashSet<String>,
+    current_class: Option<String>,
+    // Name and declared return type of the function being checked
+    current_function: Option<(String, Type)>,
+    // Signatures by the line and column of the function name, so the annotations of
+    // a function are looked at, and reported, once per pass
+    signatures: HashMap<(usize, usize), Rc<Signature>>,
+    // With the line and column they are sorted by, since functions are checked
+    // before the statements around them
+    errors: Vec<((usize, usize), String)>,
+}
+
+impl Default for Checker {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Checker {
+    pub fn new() -> Self {
+        Self {
+            scopes: vec![HashMap::new()],
+            classes: HashMap::new(),
+            type_names: HashSet::new(),
+            reassigned: HashSet::new(),
+            current_class: None,
+            current_function: None,
+            signatures: HashMap::new(),
+            errors: vec![],
+        }
+    }
+
+    pub fn check(&mut self, stmts: &[&Stmt]) -> Result<(), String> {
+        // The first pass learns the classes and which variables are reassigned, so
+        // the second one can check uses before declarations and inside loops
+        self.check_many(stmts);
+        self.errors.clear();
+        self.signatures.clear();
+        self.scopes = vec![HashMap::new()];
+        self.check_many(stmts);
+
+        if self.errors.is_empty() {
+            Ok(())
+        } else {
+            let mut errors = std::mem::take(&mut self.errors);
+            errors.sort_by_key(|(position, _)| *position);
+            let errors: Vec<String> = errors.into_iter().map(|(_, msg)| msg).collect();
+            Err(errors.join("\n"))
+        }
+    }
+
+    fn check_many(&mut self, stmts: &[&Stmt]) {
+        // Functions can be called before their declaration
+        for stmt in stmts {
+            if let Stmt::Function {
+                name,
+                params,
+                return_type,
+                body,
+                is_async,
+            } = stmt
+            {
+                let signature = self.signature(name, params, return_type, body, *is_async);
+                self.define(&name.lexeme, Typed::inferred(Type::Function(signature)));
+            }
+        }
+
+        for stmt in stmts {
+            self.check_stmt(stmt);
+        }
+    }
+
+    fn check_stmt(&mut self, stmt: &Stmt) {
+        match stmt {
+            Stmt::Expression { expression } | Stmt::Print { expression } => {
+                self.check_expr(expression);
+            }
+            Stmt::Var {
+                name,
+                annotation,
+                initializer,
+            } => {
+                let value = self.check_expr(initializer);
+                let typed = match annotation {
+                    Some(annotation) => {
+                        let ty = self.annotated_type(annotation);
+                        if !self.is_assignable(&ty, &value.ty) {
+                            self.error(
+                                name,
+                                format!(
+                                    "Cannot initialize '{}' of type {} with {}",
+                                    name.lexeme, ty, value.ty
+                                ),
+                            );
+                        }
+                        Typed { ty, declared: true }
+                    }
+                    None if self.reassigned.contains(&name.lexeme) => Typed::any(),
+                    None => value,
+                };
+                self.define(&name.lexeme, typed);
+            }
+            Stmt::Const { name, initializer } => {
+                let value = self.check_expr(initializer);
+                self.define(&name.lexeme, value);
+            }
+            Stmt::Destructure {
+                keyword: _,
+                pattern,
+                initializer,
+            } => {
+                self.check_expr(initializer);
+                for name in pattern.bindings() {
+                    self.define(&name.lexeme, Typed::any());
+                }
+            }
+            Stmt::Block { statements } => {
+                self.scopes.push(HashMap::new());
+                self.check_many(&statements.iter().map(|b| b.as_ref()).collect::<Vec<_>>());
+                self.scopes.pop();
+            }
+            Stmt::Class {
+                name,
+                fields,
+                methods,
+                superclass,
+                traits,
+            } => {
+                let superclass = match superclass {
+                    Some(Expr::Variable { id: _, name }) => Some(name.lexeme.clone()),
+                    _ => None,
+                };
+                let traits = traits
+                    .iter()
+                    .filter_map(|trait_| match trait_ {
+                        Expr::Variable { id: _, name } => Some(name.lexeme.clone()),
+                        _ => None,
+                    })
+                    .collect();
+                let info = ClassInfo {
+                    superclass,
+                    traits,
+                    fields: self.field_types(fields),
+                    methods: self.method_signatures(methods),
+                };
+                self.classes.insert(name.lexeme.clone(), info);
+                self.define(
+                    &name.lexeme,
+                    Typed::inferred(Type::Class(name.lexeme.clone())),
+                );
+
+                self.check_methods(Some(&name.lexeme), methods);
+            }
+            Stmt::Trait { name, methods } => {
+                self.type_names.insert(name.lexeme.clone());
+                self.define(&name.lexeme, Typed::any());
+                self.check_methods(None, methods);
+            }
+            Stmt::Enum { name, variants: _ } => {
+                self.type_names.insert(name.lexeme.clone());
+                self.define(&name.lexeme, Typed::any());
+            }
+            Stmt::Extend { class, methods } => {
+                let class_name = match class {
+                    Expr::Variable { id: _, name } if self.classes.contains_key(&name.lexeme) => {
+                        Some(name.lexeme.clone())
+                    }
+                    _ => None,
+                };
+                if let Some(class_name) = &class_name {
+                    let signatures = self.method_signatures(methods);
+                    if let Some(info) = self.classes.get_mut(class_name) {
+                        info.methods.extend(signatures);
+                    }
+                }
+                self.check_methods(class_name.as_deref(), methods);
+            }
+            Stmt::IfStmt {
+                predicate,
+                then,
+                els,
+            } => {
+                self.check_expr(predicate);
+                self.check_stmt(then);
+                if let Some(els) = els {
+                    self.check_stmt(els);
+                }
+            }
+            Stmt::WhileStmt { condition, body } => {
+                self.check_expr(condition);
+                self.check_stmt(body);
+            }
+            Stmt::ForIn {
+                variable,
+                iterable,
+                body,
+            } => {
+                self.check_expr(iterable);
+                self.scopes.push(HashMap::new());
+                self.define(&variable.lexeme, Typed::any());
+                self.check_stmt(body);
+                self.scopes.pop();
+            }
+            Stmt::Function {
+                name,
+                params,
+                return_type,
+                body,
+                is_async,
+            } => {
+                let signature = self.signature(name, params, return_type, body, *is_async);
+                self.define(
+                    &name.lexeme,
+                    Typed::inferred(Type::Function(signature.clone())),
+                );
+                self.check_function(&signature, params, body);
+            }
+            Stmt::AbstractMethod { .. } => (),
+            Stmt::CmdFunction {
+                name,
+                cmd: _,
+                is_async: _,
+            } => {
+                self.define(&name.lexeme, Typed::inferred(Type::Callable));
+            }
+            Stmt::Yield { keyword: _, value } => {
+                if let Some(value) = value {
+                    self.check_expr(value);
+                }
+            }
+            Stmt::ReturnStmt { keyword, value } => {
+                let value = match value {
+                    Some(value) => self.check_expr(value),
+                    None => Typed::inferred(Type::Nil),
+                };
+                if let Some((name, returns)) = self.current_function.clone() {
+                    if !self.is_assignable(&returns, &value.ty) {
+                        self.error(
+                            keyword,
+                            format!(
+                                "'{}' must return {} but returns {}",
+                                name, returns, value.ty
+                            ),
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    fn check_methods(&mut self, class: Option<&str>, methods: &[Box<Stmt>]) {
+        let enclosing_class = self.current_class.take();
+        self.current_class = class.map(|c| c.to_string());
+        for method in methods {
+            if let Stmt::Function {
+                name,
+                params,
+                return_type,
+                body,
+                is_async,
+            } = method.as_ref()
+            {
+                let signature = self.signature(name, params, return_type, body, *is_async);
+                self.check_function(&signature, params, body);
+            }
+        }
+        self.current_class = enclosing_class;
+    }
+
+    fn check_function(&mut self, signature: &Signature, params: &[Parameter], body: &[Box<Stmt>]) {
+        // Only annotated return types are checked, and generators return no values
+        let returns = match signature.returns {
+            Type::Any | Type::Generator => None,
+            ref returns => Some((signature.name.clone(), returns.clone())),
+        };
+        let enclosing_function = std::mem::replace(&mut self.current_function, returns);
+
+        self.scopes.push(HashMap::new());
+        for param in params {
+            if let Some(default) = &param.default {
+                self.check_expr(default);
+            }
+            // The signature already has the types of the annotated parameters
+            let annotated = signature
+                .params
+                .iter()
+                .find(|(name, _)| name == &param.name.lexeme);
+            let typed = match (&param.annotation, annotated) {
+                (Some(_), Some((_, ty))) => Typed {
+                    ty: ty.clone(),
+                    declared: true,
+                },
+                // Rest parameters are not part of the signature
+                (Some(annotation), None) => Typed {
+                    ty: self.annotated_type(annotation),
+                    declared: true,
+                },
+                _ if param.is_rest => Typed::inferred(Type::List),
+                _ => Typed::any(),
+            };
+            self.define(&param.name.lexeme, typed);
+            if let Some(pattern) = &param.pattern {
+                for name in pattern.bindings() {
+                    self.define(&name.lexeme, Typed::any());
+                }
+            }
+        }
+        self.check_many(&body.iter().map(|b| b.as_ref()).collect::<Vec<_>>());
+        self.scopes.pop();
+
+        self.current_function = enclosing_function;
+    }
+
+    fn check_expr(&mut self, expr: &Expr) -> Typed {
+        match expr {
+            Expr::AnonFunction {
+                id: _,
+                paren: _,
+                arguments,
+                body,
+            } => {
+                let signature = Rc::new(Signature {
+                    name: "anonymous function".to_string(),
+                    params: self.param_types(arguments),
+                    returns: Type::Any,
+                    is_async: false,
+                });
+                self.check_function(&signature, arguments, body);
+                Typed::inferred(Type::Function(signature))
+            }
+            Expr::Assign { id: _, name, value } => {
+                let value = self.check_expr(value);
+                self.reassigned.insert(name.lexeme.clone());
+                if let Some(target) = self.lookup(&name.lexeme) {
+                    if target.declared && !self.is_assignable(&target.ty, &value.ty) {
+                        self.error(
+                            name,
+                            format!(
+                                "Cannot assign {} to '{}' of type {}",
+                                value.ty, name.lexeme, target.ty
+                            ),
+                        );
+                    }
+                }
+                value
+            }
+            Expr::AssignTuple {
+                id: _,
+                targets,
+                value,
+            } => {
+                let value = self.check_expr(value);
+                self.mark_reassigned(targets);
+                value
+            }
+            // Tasks do not know the type of their result
+            Expr::Await {
+                id: _,
+                keyword: _,
+                value,
+            } => {
+                self.check_expr(value);
+                Typed::any()
+            }
+            Expr::Binary {
+                id: _,
+                left,
+                operator,
+                right,
+            } => {
+                let left = self.check_expr(left);
+                let right = self.check_expr(right);
+                let declared = left.declared || right.declared;
+                match binary_type(operator.token_type, &left.ty, &right.ty) {
+                    Some(ty) => Typed { ty, declared },
+                    None => {
+                        if declared {
+                            self.error(
+                                operator,
+                                format!(
+                                    "Operator '{}' cannot be applied to {} and {}",
+                                    operator.lexeme, left.ty, right.ty
+                                ),
+                            );
+                        }
+                        Typed::any()
+                    }
+                }
+            }
+            Expr::Call {
+                id: _,
+                callee,
+                paren,
+                arguments,
+                named_arguments,
+            } => {
+                let callee = self.check_expr(callee);
+                let arguments: Vec<Typed> = arguments.iter().map(|a| self.check_expr(a)).collect();
+                let named_arguments: Vec<(&Token, Typed)> = named_arguments
+                    .iter()
+                    .map(|(name, value)| (name, self.check_expr(value)))
+                    .collect();
+
+                let (signature, result) = match &callee.ty {
+                    Type::Function(signature) if signature.is_async => {
+                        (Some(signature.clone()), Typed::inferred(Type::Task))
+                    }
+                    Type::Function(signature) => (
+                        Some(signature.clone()),
+                        Typed {
+                            ty: signature.returns.clone(),
+                            declared: signature.returns != Type::Any,
+                        },
+                    ),
+                    Type::Class(class) => (
+                        self.find_method(class, "init"),
+                        Typed::inferred(Type::Named(class.clone())),
+                    ),
+                    _ => (None, Typed::any()),
+                };
+
+                if let Some(signature) = signature {
+                    for (i, argument) in arguments.iter().enumerate() {
+                        if let Some((_, ty)) = signature.params.get(i) {
+                            self.check_argument(
+                                paren,
+                                &signature,
+                                &(i + 1).to_string(),
+                                ty,
+                                argument,
+                            );
+                        }
+                    }
+                    for (name, argument) in named_arguments.iter() {
+                        let param = signature.params.iter().find(|(p, _)| p == &name.lexeme);
+                        if let Some((_, ty)) = param {
+                            self.check_argument(name, &signature, &name.lexeme, ty, argument);
+                        }
+                    }
+                }
+                result
+            }
+            Expr::Get {
+                id: _,
+                object,
+                name,
+            } => {
+                let object = self.check_expr(object);
+                match &object.ty {
+                    Type::Named(class) => {
+                        if let Some(ty) = self.find_field(class, &name.lexeme) {
+                            Typed { ty, declared: true }
+                        } else if let Some(method) = self.find_method(class, &name.lexeme) {
+                            Typed::inferred(Type::Function(method))
+                        } else {
+                            Typed::any()
+                        }
+                    }
+                    _ => Typed::any(),
+                }
+            }
+            Expr::Grouping { id: _, expression } => self.check_expr(expression),
+            Expr::Index {
+                id: _,
+                object,
+                bracket: _,
+                index,
+            } => {
+                self.check_expr(object);
+                self.check_expr(index);
+                Typed::any()
+            }
+            Expr::List { id: _, elements } => {
+                for element in elements {
+                    self.check_expr(element);
+                }
+                Typed::inferred(Type::List)
+            }
+            Expr::Tuple { id: _, elements } => {
+                for element in elements {
+                    self.check_expr(element);
+                }
+                Typed::inferred(Type::Tuple)
+            }
+            Expr::Literal { id: _, value } => Typed::inferred(literal_type(value)),
+            Expr::Logical {
+                id: _,
+                left,
+                operator: _,
+                right,
+            } => {
+                self.check_expr(left);
+                self.check_expr(right);
+                Typed::any()
+            }
+            Expr::Match {
+                id: _,
+                keyword: _,
+                subject,
+                arms,
+            } => {
+                self.check_expr(subject);
+                for arm in arms {
+                    self.scopes.push(HashMap::new());
+                    for name in arm.pattern.bindings() {
+                        self.define(&name.lexeme, Typed::any());
+                    }
+                    if let Some(guard) = &arm.guard {
+                        self.check_expr(guard);
+                    }
+                    self.check_expr(&arm.body);
+                    self.scopes.pop();
+                }
+                Typed::any()
+            }
+            Expr::Set {
+                id: _,
+                object,
+                name,
+                value,
+            } => {
+                let object = self.check_expr(object);
+                let value = self.check_expr(value);
+                if let Type::Named(class) = &object.ty {
+                    if let Some(ty) = self.find_field(class, &name.lexeme) {
+                        if !self.is_assignable(&ty, &value.ty) {
+                            self.error(
+                                name,
+                                format!(
+                                    "Cannot assign {} to field '{}' of type {}",
+                                    value.ty, name.lexeme, ty
+                                ),
+                            );
+                        }
+                    }
+                }
+                value
+            }
+            Expr::SetIndex {
+                id: _,
+                object,
+                bracket: _,
+                index,
+                value,
+            } => {
+                self.check_expr(object);
+                self.check_expr(index);
+                self.check_expr(value)
+            }
+            Expr::This { id: _, keyword: _ } => match &self.current_class {
+                Some(class) => Typed::inferred(Type::Named(class.clone())),
+                None => Typed::any(),
+            },
+            Expr::Super { .. } => Typed::any(),
+            Expr::Unary {
+                id: _,
+                operator,
+                right,
+            } => {
+                let right = self.check_expr(right);
+                match (operator.token_type, &right.ty) {
+                    (TokenType::Bang, _) => Typed::inferred(Type::Boolean),
+                    (TokenType::Minus, Type::Number) => right,
+                    (TokenType::Minus, Type::Any | Type::Named(_)) => Typed::any(),
+                    (_, ty) => {
+                        if right.declared {
+                            self.error(
+                                operator,
+                                format!(
+                                    "Operator '{}' cannot be applied to {}",
+                                    operator.lexeme, ty
+                                ),
+                            );
+                        }
+                        Typed::any()
+                    }
+                }
+            }
+            Expr::Variable { id: _, name } => self.lookup(&name.lexeme).unwrap_or_else(Typed::any),
+        }
+    }
+
+    fn check_argument(
+        &mut self,
+        token: &Token,
+        signature: &Signature,
+        argument_name: &str,
+        expected: &Type,
+        argument: &Typed,
+    ) {
+        if !self.is_assignable(expected, &argument.ty) {
+            self.error(
+                token,
+                format!(
+                    "Argument {} of '{}' must be {} but got {}",
+                    argument_name, signature.name, expected, argument.ty
+                ),
+            );
+        }
+    }
+
+    fn mark_reassigned(&mut self, targets: &[Expr]) {
+        for target in targets {
+            match target {
+                Expr::Variable { id: _, name } => {
+                    self.reassigned.insert(name.lexeme.clone());
+                }
+                Expr::Tuple { id: _, elements } => self.mark_reassigned(elements),
+                _ => (),
+            }
+        }
+    }
+
+    fn signature(
+        &mut self,
+        name: &Token,
+        params: &[Parameter],
+        returns: &Option<Token>,
+        body: &[Box<Stmt>],
+        is_async: bool,
+    ) -> Rc<Signature> {
+        let key = (name.line_number, name.column);
+        if let Some(signature) = self.signatures.get(&key) {
+            return signature.clone();
+        }
+
+        let is_generator = contains_yield(body);
+        let returns = match returns {
+            Some(annotation) => {
+                let returns = self.annotated_type(annotation);
+                if is_generator && !matches!(returns, Type::Generator | Type::Any) {
+                    self.error(
+                        annotation,
+                        format!("'{}' yields, so it returns Generator", name.lexeme),
+                    );
+                }
+                returns
+            }
+            None if is_generator => Type::Generator,
+            None => Type::Any,
+        };
+        let signature = Rc::new(Signature {
+            name: name.lexeme.clone(),
+            params: self.param_types(params),
+            returns,
+            is_async,
+        });
+        self.signatures.insert(key, signature.clone());
+        signature
+    }
+
+    fn param_types(&mut self, params: &[Parameter]) -> Vec<(String, Type)> {
+        params
+            .iter()
+            .filter(|param| !param.is_rest)
+            .map(|param| {
+                let ty = match &param.annotation {
+                    Some(annotation) => self.annotated_type(annotation),
+                    None => Type::Any,
+                };
+                (param.name.lexeme.clone(), ty)
+            })
+            .collect()
+    }
+
+    fn method_signatures(&mut self, methods: &[Box<Stmt>]) -> HashMap<String, Rc<Signature>> {
+        let mut signatures = HashMap::new();
+        for method in methods {
+            if let Stmt::Function {
+                name,
+                params,
+                return_type,
+                body,
+                is_async,
+            } = method.as_ref()
+            {
+                let signature = self.signature(name, params, return_type, body, *is_async);
+                signatures.insert(name.lexeme.clone(), signature);
+            }
+        }
+        signatures
+    }
+
+    fn field_types(&mut self, fields: &[Field]) -> HashMap<String, Type> {
+        fields
+            .iter()
+            .map(|field| {
+                (
+                    field.name.lexeme.clone(),
+                    self.annotated_type(&field.annotation),
+                )
+            })
+            .collect()
+    }
+
+    fn annotated_type(&mut self, annotation: &Token) -> Type {
+        match annotation.lexeme.as_str() {
+            "Any" => Type::Any,
+            "nil" => Type::Nil,
+            "Number" => Type::Number,
+            "String" => Type::String,
+            "Boolean" => Type::Boolean,
+            "List" => Type::List,
+            "Tuple" => Type::Tuple,
+            "Range" => Type::Range,
+            "Generator" => Type::Generator,
+            "Task" => Type::Task,
+            "Callable" => Type::Callable,
+            name if self.classes.contains_key(name) || self.type_names.contains(name) => {
+                Type::Named(name.to_string())
+            }
+            name => {
+                self.error(annotation, format!("Unknown type '{}'", name));
+                Type::Any
+            }
+        }
+    }
+
+    fn is_assignable(&self, expected: &Type, actual: &Type) -> bool {
+        match (expected, actual) {
+            // nil stands in for a missing value of any type
+            (Type::Any, _) | (_, Type::Any) | (_, Type::Nil) => true,
+            (Type::Callable | Type::Function(_), Type::Callable | Type::Function(_)) => true,
+            (Type::Callable, Type::Class(_)) => true,
+            (Type::Named(expected), Type::Named(actual)) => self.is_subtype(actual, expected),
+            (expected, actual) => expected == actual,
+        }
+    }
+
+    // True if instances of the class are instances of the class or trait `expected`
+    fn is_subtype(&self, class: &str, expected: &str) -> bool {
+        let mut current = Some(class);
+        while let Some(class) = current {
+            if class == expected {
+                return true;
+            }
+            let info = match self.classes.get(class) {
+                Some(info) => info,
+                None => return false,
+            };
+            if info.traits.iter().any(|t| t == expected) {
+                return true;
+            }
+            current = info.superclass.as_deref();
+        }
+        false
+    }
+
+    fn find_field(&self, class: &str, name: &str) -> Option<Type> {
+        let info = self.classes.get(class)?;
+        match info.fields.get(name) {
+            Some(ty) => Some(ty.clone()),
+            None => self.find_field(info.superclass.as_deref()?, name),
+        }
+    }
+
+    fn find_method(&self, class: &str, name: &str) -> Option<Rc<Signature>> {
+        let info = self.classes.get(class)?;
+        match info.methods.get(name) {
+            Some(signature) => Some(signature.clone()),
+            None => self.find_method(info.superclass.as_deref()?, name),
+        }
+    }
+
+    fn define(&mut self, name: &str, typed: Typed) {
+        self.scopes
+            .last_mut()
+            .expect("Checker has no scope")
+            .insert(name.to_string(), typed);
+    }
+
+    fn lookup(&self, name: &str) -> Option<Typed> {
+        self.scopes
+            .iter()
+            .rev()
+            .find_map(|scope| scope.get(name).cloned())
+    }
+
+    fn error(&mut self, token: &Token, msg: String) {
+        let position = (token.line_number, token.column);
+        self.errors.push((
+            position,
+            format!(
+                "Line {}, column {} at '{}': {}",
+                token.line_number, token.column, token.lexeme, msg
+            ),
+        ));
+    }
+}
+
+fn literal_type(value: &LiteralValue) -> Type {
+    match value {
+        LiteralValue::Number(_) => Type::Number,
+        LiteralValue::StringValue(_) => Type::String,
+        LiteralValue::True | LiteralValue::False => Type::Boolean,
+        LiteralValue::Nil => Type::Nil,
+        _ => Type::Any,
+    }
+}
+
+// The type of `left op right`, or None if running it is bound to fail
+fn binary_type(operator: TokenType, left: &Type, right: &Type) -> Option<Type> {
+    use TokenType::*;
+
+    match (left, operator, right) {
+        (_, Is, _) => Some(Type::Boolean),
+        // Instances can overload operators
+        (Type::Any | Type::Named(_), _, _) | (_, _, Type::Any) => Some(Type::Any),
+        (_, EqualEqual | BangEqual, _) => Some(Type::Boolean),
+        (Type::Number, Plus | Minus | Star | Slash, Type::Number) => Some(Type::Number),
+        (Type::Number, Greater | GreaterEqual | Less | LessEqual, Type::Number) => {
+            Some(Type::Boolean)
+        }
+        (Type::Number, DotDot | DotDotEqual, Type::Number) => Some(Type::Range),
+        (Type::String, Plus, Type::String) => Some(Type::String),
+        (Type::String, Greater | GreaterEqual | Less | LessEqual, Type::String) => {
+            Some(Type::Boolean)
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::Parser;
+    use crate::scanner::Scanner;
+
+    fn check(source: &str) -> Result<(), String> {
+        let tokens = Scanner::new(source).scan_tokens().unwrap();
+        let stmts = Parser::new(tokens).parse().unwrap();
+        Checker::new().check(&stmts.iter().collect::<Vec<_>>())
+    }
+
+    #[test]
+    fn unannotated_code_is_not_checked() {
+        assert!(check("var a = \"a\"; print a + 1; a = 2;").is_ok());
+        assert!(check("fun f(x) { return x + 1; } f(\"a\");").is_ok());
+    }
+
+    #[test]
+    fn locals_are_inferred() {
+        let errors = check("fun f(x: Number) {} var s = \"a\"; f(s);").unwrap_err();
+        assert_eq!(
+            errors,
+            "Line 1, column 37 at ')': Argument 1 of 'f' must be Number but got String"
+        );
+        // Reassigned variables can hold anything
+        assert!(check("fun f(x: Number) {} var s = \"a\"; s = 1; f(s);").is_ok());
+    }
+
+    #[test]
+    fn fields_and_subclasses() {
+        let source = "
+            class Animal { name: String; }
+            class Dog < Animal {}
+            fun greet(a: Animal) -> String { return a.name; }
+            greet(Dog());
+            Dog().name = 3;
+        ";
+        let errors = check(source).unwrap_err();
+        assert_eq!(
+            errors,
+            "Line 6, column 19 at 'name': Cannot assign Number to field 'name' of type String"
+        );
+    }
+}
@@ -1,43 +1,104 @@
-use crate::expr::{CallableImpl, LiteralValue, NativeFunctionImpl};
+use crate::expr::LiteralValue;
+use crate::natives;
+use crate::sandbox::{Config, Runtime};
 use std::cell::RefCell;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::rc::Rc;
 
 #[derive(Clone)]
 pub struct Environment {
     pub values: Rc<RefCell<HashMap<String, LiteralValue>>>,
+    constants: Rc<RefCell<HashSet<String>>>,
     locals: Rc<RefCell<HashMap<usize, usize>>>,
     pub enclosing: Option<Box<Environment>>,
+    pub runtime: Rc<Runtime>,
 }
 
-fn clock_impl(_args: &Vec<LiteralValue>) -> LiteralValue {
+fn clock_impl(_args: &[LiteralValue]) -> Result<LiteralValue, String> {
     let now = std::time::SystemTime::now()
         .duration_since(std::time::SystemTime::UNIX_EPOCH)
         .expect("Could not get system time")
         .as_millis();
 
-    LiteralValue::Number(now as f64 / 1000.0)
+    Ok(LiteralValue::Number(now as f64 / 1000.0))
+}
+
+fn freeze_impl(args: &[LiteralValue]) -> Result<LiteralValue, String> {
+    match &args[0] {
+        LiteralValue::LoxInstance { frozen, .. } => {
+            *frozen.borrow_mut() = true;
+            Ok(args[0].clone())
+        }
+        other => Err(format!("Cannot freeze value of type {}", other.to_type())),
+    }
+}
+
+fn is_frozen_impl(args: &[LiteralValue]) -> Result<LiteralValue, String> {
+    match &args[0] {
+        LiteralValue::LoxInstance { frozen, .. } => Ok(LiteralValue::from_bool(*frozen.borrow())),
+        _ => Ok(LiteralValue::False),
+    }
+}
+
+fn len_impl(args: &[LiteralValue]) -> Result<LiteralValue, String> {
+    match &args[0] {
+        LiteralValue::StringValue(s) => Ok(LiteralValue::Number(s.chars().count() as f64)),
+        LiteralValue::List(items) => Ok(LiteralValue::Number(items.borrow().len() as f64)),
+        other => Err(format!("Cannot get length of type {}", other.to_type())),
+    }
+}
+
+fn push_impl(args: &[LiteralValue]) -> Result<LiteralValue, String> {
+    match &args[0] {
+        LiteralValue::List(items) => {
+            items.borrow_mut().push(args[1].clone());
+            Ok(args[0].clone())
+        }
+        other => Err(format!("Cannot push to type {}", other.to_type())),
+    }
+}
+
+fn define_native(
+    env: &mut HashMap<String, LiteralValue>,
+    name: &str,
+    arity: usize,
+    fun: natives::NativeFn,
+) {
+    env.insert(name.to_string(), natives::native(name, arity, fun));
 }
 
 fn get_globals() -> Rc<RefCell<HashMap<String, LiteralValue>>> {
     let mut env = HashMap::new();
-    let fun_impl = NativeFunctionImpl {
-        name: "clock".to_string(),
-        arity: 0,
-        fun: Rc::new(clock_impl),
-    };
-    let callable_impl = CallableImpl::NativeFunction(fun_impl);
-    env.insert("clock".to_string(), LiteralValue::Callable(callable_impl));
+    define_native(&mut env, "clock", 0, clock_impl);
+    define_native(&mut env, "freeze", 1, freeze_impl);
+    define_native(&mut env, "is_frozen", 1, is_frozen_impl);
+    define_native(&mut env, "len", 1, len_impl);
+    define_native(&mut env, "push", 2, push_impl);
+    natives::register(&mut env);
 
     Rc::new(RefCell::new(env))
 }
 
 impl Environment {
     pub fn new(locals: HashMap<usize, usize>) -> Self {
+        Self::with_config(locals, Config::default())
+    }
+
+    pub fn with_config(locals: HashMap<usize, usize>, config: Config) -> Self {
+        let values = get_globals();
+        let constants = Rc::new(RefCell::new(HashSet::new()));
+        let runtime = Rc::new(Runtime::new(config));
+        natives::tasks::register(&mut values.borrow_mut(), &runtime.event_loop);
+        natives::iter::register(&mut values.borrow_mut(), &runtime);
+        natives::threads::register(&values, &constants, &runtime);
+        natives::restrict(&mut values.borrow_mut(), &runtime.config);
+
         Self {
-            values: get_globals(),
+            values,
+            constants,
             locals: Rc::new(RefCell::new(locals)),
             enclosing: None,
+            runtime,
         }
     }
 
@@ -51,20 +112,50 @@ impl Environment {
     pub fn enclose(&self) -> Environment {
         Self {
             values: Rc::new(RefCell::new(HashMap::new())),
+            constants: Rc::new(RefCell::new(HashSet::new())),
             locals: self.locals.clone(),
             enclosing: Some(Box::new(self.clone())),
+            runtime: self.runtime.clone(),
         }
     }
 
+    // For bindings the interpreter makes itself, like parameters and 'this'
     pub fn define(&self, name: String, value: LiteralValue) {
         self.values.borrow_mut().insert(name, value);
     }
 
+    // For declarations in a script, which can not replace a constant
+    pub fn declare(&self, name: String, value: LiteralValue) -> Result<(), String> {
+        if self.constants.borrow().contains(&name) {
+            return Err(format!("Cannot redeclare constant '{}'", name));
+        }
+        self.define(name, value);
+        Ok(())
+    }
+
+    pub fn define_const(&self, name: String, value: LiteralValue) -> Result<(), String> {
+        self.declare(name.clone(), value)?;
+        self.constants.borrow_mut().insert(name);
+        Ok(())
+    }
+
     pub fn get(&self, name: &str, expr_id: usize) -> Option<LiteralValue> {
         let distance = self.locals.borrow().get(&expr_id).cloned();
         self.get_internal(name, distance)
     }
 
+    // For names the resolver does not see, like the class in a match pattern. Finds
+    // the innermost binding with the name
+    pub fn lookup(&self, name: &str) -> Option<LiteralValue> {
+        if let Some(value) = self.values.borrow().get(name) {
+            return Some(value.clone());
+        }
+        match &self.enclosing {
+            Some(env) => env.lookup(name),
+            None => None,
+        }
+    }
+
     pub fn get_this_instance(&self, super_id: usize) -> Option<LiteralValue> {
         let distance = self
             .locals
@@ -101,36 +192,46 @@ impl Environment {
         }
     }
 
-    pub fn assign_global(&self, name: &str, value: LiteralValue) -> bool {
-        self.assign_internal(name, value, None)
+    // Variables the resolver did not find in a local scope are globals
+    pub fn assign_global(&self, name: &str, value: LiteralValue) -> Result<(), String> {
+        if let Some(env) = &self.enclosing {
+            return env.assign_global(name, value);
+        }
+        if self.constants.borrow().contains(name) {
+            return Err(format!("Cannot assign to constant '{}'", name));
+        }
+        if !self.values.borrow().contains_key(name) {
+            return Err(format!("Variable {} has not been declared", name));
+        }
+        self.values.borrow_mut().insert(name.to_string(), value);
+        Ok(())
     }
 
-    pub fn assign(&self, name: &str, value: LiteralValue, expr_id: usize) -> bool {
+    pub fn assign(&self, name: &str, value: LiteralValue, expr_id: usize) -> Result<(), String> {
         // ! Important that this ID matches with the resolver
         let distance = self.locals.borrow().get(&expr_id).cloned();
-        self.assign_internal(name, value, distance)
+        match distance {
+            Some(distance) => self.assign_internal(name, value, distance),
+            None => self.assign_global(name, value),
+        }
     }
 
-    fn assign_internal(&self, name: &str, value: LiteralValue, distance: Option<usize>) -> bool {
-        if let None = distance {
-            match &self.enclosing {
-                Some(env) => env.assign_internal(name, value, distance),
-                None => match self.values.borrow_mut().insert(name.to_string(), value) {
-                    Some(_) => true,
-                    None => false,
-                },
+    fn assign_internal(
+        &self,
+        name: &str,
+        value: LiteralValue,
+        distance: usize,
+    ) -> Result<(), String> {
+        if distance == 0 {
+            if self.constants.borrow().contains(name) {
+                return Err(format!("Cannot assign to constant '{}'", name));
             }
+            self.values.borrow_mut().insert(name.to_string(), value);
+            Ok(())
         } else {
-            let distance = distance.unwrap();
-            if distance == 0 {
-                self.values.borrow_mut().insert(name.to_string(), value);
-                true
-            } else {
-                match &self.enclosing {
-                    None => panic!("Tried to define a variable in a too deep level"),
-                    Some(env) => env.assign_internal(name, value, Some(distance - 1)),
-                };
-                true
+            match &self.enclosing {
+                None => panic!("Tried to define a variable in a too deep level"),
+                Some(env) => env.assign_internal(name, value, distance - 1),
             }
         }
     }
@@ -159,4 +260,43 @@ mod tests {
     fn try_init() {
         let _environment = Environment::new(HashMap::new());
     }
+
+    #[test]
+    fn assign_global_refuses_constants() {
+        let environment = Environment::new(HashMap::new());
+        environment.define("a".to_string(), LiteralValue::Number(1.0));
+        environment
+            .define_const("b".to_string(), LiteralValue::Number(2.0))
+            .unwrap();
+
+        assert!(environment
+            .assign_global("a", LiteralValue::Number(3.0))
+            .is_ok());
+        assert!(environment
+            .assign_global("b", LiteralValue::Number(3.0))
+            .is_err());
+        assert!(environment
+            .assign_global("c", LiteralValue::Number(3.0))
+            .is_err());
+        assert_eq!(environment.get("b", 0), Some(LiteralValue::Number(2.0)));
+    }
+
+    #[test]
+    fn constants_can_not_be_redeclared() {
+        let environment = Environment::new(HashMap::new());
+        environment
+            .define_const("a".to_string(), LiteralValue::Number(1.0))
+            .unwrap();
+
+        assert_eq!(
+            environment
+                .declare("a".to_string(), LiteralValue::Number(2.0))
+                .unwrap_err(),
+            "Cannot redeclare constant 'a'"
+        );
+        assert!(environment
+            .define_const("a".to_string(), LiteralValue::Number(2.0))
+            .is_err());
+        assert_eq!(environment.get("a", 0), Some(LiteralValue::Number(1.0)));
+    }
 }
@@ -0,0 +1,420 @@
+use crate::expr::{call_value, LiteralValue};
+use crate::generator::{Coroutine, Suspend};
+use std::cell::{Cell, RefCell};
+use std::collections::VecDeque;
+use std::process::Command;
+use std::rc::Rc;
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+// How long the loop waits before checking on running commands again
+const POLL_INTERVAL: Duration = Duration::from_millis(1);
+
+enum TaskState {
+    Pending,
+    Done(LiteralValue),
+    Failed(String),
+}
+
+enum TaskBody {
+    // The body of an async function
+    Coroutine(Coroutine),
+    // A function passed to spawn(), which is called the first time the task runs
+    Call(RefCell<Option<LiteralValue>>),
+    // A timer, command or channel receive, completed by the event loop
+    External,
+}
+
+// Something `await` waits for: an async function that is running, or a timer, a
+// command or a receive from a channel that completes later
+pub struct TaskImpl {
+    pub name: String,
+    body: TaskBody,
+    state: RefCell<TaskState>,
+    // Tasks suspended until this one is done
+    waiters: RefCell<Vec<Rc<TaskImpl>>>,
+}
+
+impl TaskImpl {
+    fn new(name: String, body: TaskBody) -> Self {
+        Self {
+            name,
+            body,
+            state: RefCell::new(TaskState::Pending),
+            waiters: RefCell::new(vec![]),
+        }
+    }
+
+    pub fn coroutine(name: String, coroutine: Coroutine) -> Self {
+        Self::new(name, TaskBody::Coroutine(coroutine))
+    }
+
+    // None while the task has not finished
+    pub fn result(&self) -> Option<Result<LiteralValue, String>> {
+        match &*self.state.borrow() {
+            TaskState::Pending => None,
+            TaskState::Done(value) => Some(Ok(value.clone())),
+            TaskState::Failed(error) => Some(Err(error.clone())),
+        }
+    }
+
+    fn resume(&self, sent: Result<LiteralValue, String>) -> Result<Suspend, String> {
+        match &self.body {
+            TaskBody::Coroutine(coroutine) => match coroutine.resume(sent)? {
+                Suspend::Yield(_) => Err(format!("Task {} cannot yield", self.name)),
+                suspend => Ok(suspend),
+            },
+            // An async function returns a task, which this one waits for
+            TaskBody::Call(callee) => match callee.take() {
+                Some(callee) => match call_value(callee, vec![], vec![])? {
+                    task @ LiteralValue::Task(_) => Ok(Suspend::Await(task)),
+                    value => Ok(Suspend::Return(value)),
+                },
+                None => Ok(Suspend::Return(sent?)),
+            },
+            TaskBody::External => Err(format!("Task {} cannot be resumed", self.name)),
+        }
+    }
+}
+
+// A task that can continue, with the result of what it waited for
+type Resumption = (Rc<TaskImpl>, Result<LiteralValue, String>);
+
+// A task for a running command, and where its stdout arrives
+type RunningCommand = (Rc<TaskImpl>, Receiver<Result<String, String>>);
+
+struct Timer {
+    deadline: u64,
+    task: Rc<TaskImpl>,
+}
+
+// Runs tasks one at a time until they suspend, on the thread of the interpreter.
+// Timers run on a virtual clock, so the order in which tasks wake up does not
+// depend on how fast the machine is. Only commands run in parallel.
+pub struct EventLoop {
+    ready: RefCell<VecDeque<Resumption>>,
+    // Ordered by deadline, timers with the same deadline fire in the order they were set
+    timers: RefCell<Vec<Timer>>,
+    commands: RefCell<Vec<RunningCommand>>,
+    // Milliseconds since the loop was created, as far as its timers are concerned
+    now: Cell<u64>,
+    started: Instant,
+    // Whether timers also wait until their time has really passed. Scripts with a
+    // step budget do not wait, since waiting takes no steps.
+    real_time: bool,
+}
+
+impl Default for EventLoop {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl std::fmt::Debug for EventLoop {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "EventLoop {{ ready: {}, timers: {}, commands: {}, now: {} }}",
+            self.ready.borrow().len(),
+            self.timers.borrow().len(),
+            self.commands.borrow().len(),
+            self.now.get()
+        )
+    }
+}
+
+impl EventLoop {
+    pub fn new() -> Self {
+        Self {
+            ready: RefCell::new(VecDeque::new()),
+            timers: RefCell::new(vec![]),
+            commands: RefCell::new(vec![]),
+            now: Cell::new(0),
+            started: Instant::now(),
+            real_time: true,
+        }
+    }
+
+    // A loop whose timers fire as soon as nothing else can run
+    pub fn virtual_time() -> Self {
+        Self {
+            real_time: false,
+            ..Self::new()
+        }
+    }
+
+    // Schedules a task to run from the start
+    pub fn spawn(&self, task: Rc<TaskImpl>) {
+        self.ready
+            .borrow_mut()
+            .push_back((task, Ok(LiteralValue::Nil)));
+    }
+
+    // A task that calls the function, and waits for it if it is async
+    pub fn spawn_call(&self, name: String, callee: LiteralValue) -> Rc<TaskImpl> {
+        let task = Rc::new(TaskImpl::new(
+            name,
+            TaskBody::Call(RefCell::new(Some(callee))),
+        ));
+        self.spawn(task.clone());
+        task
+    }
+
+    // A task that is done after the given number of milliseconds
+    pub fn sleep(&self, millis: u64) -> Rc<TaskImpl> {
+        let task = Rc::new(TaskImpl::new("sleep".to_string(), TaskBody::External));
+        let deadline = self.now.get() + millis;
+
+        let mut timers = self.timers.borrow_mut();
+        let position = timers.partition_point(|timer| timer.deadline <= deadline);
+        timers.insert(
+            position,
+            Timer {
+                deadline,
+                task: task.clone(),
+            },
+        );
+        task
+    }
+
+    // Starts the command on another thread, the task is done with its stdout
+    pub fn run_command(&self, name: &str, cmd: &str, mut command: Command) -> Rc<TaskImpl> {
+        let task = Rc::new(TaskImpl::new(name.to_string(), TaskBody::External));
+        let (sender, receiver) = mpsc::channel();
+        let cmd = cmd.to_string();
+        thread::spawn(move || {
+            let result = match command.output() {
+                Ok(output) => Ok(String::from_utf8_lossy(&output.stdout).to_string()),
+                Err(e) => Err(format!("Failed to run command '{}': {}", cmd, e)),
+            };
+            let _ = sender.send(result);
+        });
+
+        self.commands.borrow_mut().push((task.clone(), receiver));
+        task
+    }
+
+    // Runs the loop until the value is ready, values that are not tasks are ready right away
+    pub fn block_on(&self, value: LiteralValue) -> Result<LiteralValue, String> {
+        let task = match value {
+            LiteralValue::Task(task) => task,
+            other => return Ok(other),
+        };
+        loop {
+            if let Some(result) = task.result() {
+                return result;
+            }
+            if !self.run_once()? {
+                return Err(format!(
+                    "Task '{}' can never finish, there is nothing left to run",
+                    task.name
+                ));
+            }
+        }
+    }
+
+    // Runs until no task can make progress
+    pub fn run(&self) -> Result<(), String> {
+        while self.run_once()? {}
+        Ok(())
+    }
+
+    // Does one thing: resumes a task, finishes a command or fires a timer.
+    // Returns false if there is nothing left to do.
+    fn run_once(&self) -> Result<bool, String> {
+        let next = self.ready.borrow_mut().pop_front();
+        if let Some((task, sent)) = next {
+            let suspend = task.resume(sent);
+            self.suspended(task, suspend)?;
+            return Ok(true);
+        }
+
+        if self.poll_commands()? {
+            return Ok(true);
+        }
+        let waiting_for_commands = !self.commands.borrow().is_empty();
+
+        let deadline = self.timers.borrow().first().map(|timer| timer.deadline);
+        match deadline {
+            Some(deadline) => {
+                // Timers never fire before their time, even though the clock is virtual
+                let wait = match self.real_time {
+                    true => Duration::from_millis(deadline).saturating_sub(self.started.elapsed()),
+                    false => Duration::ZERO,
+                };
+                if waiting_for_commands && !wait.is_zero() {
+                    thread::sleep(wait.min(POLL_INTERVAL));
+                    return Ok(true);
+                }
+                thread::sleep(wait);
+
+                let timer = self.timers.borrow_mut().remove(0);
+                self.now.set(deadline);
+                self.complete(&timer.task, Ok(LiteralValue::Nil))?;
+                Ok(true)
+            }
+            None if waiting_for_commands => {
+                thread::sleep(POLL_INTERVAL);
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    fn suspended(
+        &self,
+        task: Rc<TaskImpl>,
+        suspend: Result<Suspend, String>,
+    ) -> Result<(), String> {
+        match suspend {
+            Ok(Suspend::Await(LiteralValue::Task(awaited))) => match awaited.result() {
+                Some(result) => self.ready.borrow_mut().push_back((task, result)),
+                None => awaited.waiters.borrow_mut().push(task),
+            },
+            Ok(Suspend::Await(value)) => self.ready.borrow_mut().push_back((task, Ok(value))),
+            Ok(Suspend::Return(value)) => self.complete(&task, Ok(value))?,
+            Ok(Suspend::Yield(_)) => unreachable!("Tasks do not yield"),
+            Err(e) => self.complete(&task, Err(e))?,
+        }
+        Ok(())
+    }
+
+    // True if a command finished
+    fn poll_commands(&self) -> Result<bool, String> {
+        let finished = self
+            .commands
+            .borrow()
+            .iter()
+            .enumerate()
+            .find_map(|(i, (_, receiver))| match receiver.try_recv() {
+                Ok(result) => Some((i, result)),
+                Err(TryRecvError::Empty) => None,
+                Err(TryRecvError::Disconnected) => Some((i, Err("Command stopped".to_string()))),
+            });
+        let Some((i, result)) = finished else {
+            return Ok(false);
+        };
+
+        let (task, _) = self.commands.borrow_mut().remove(i);
+        self.complete(&task, result.map(LiteralValue::StringValue))?;
+        Ok(true)
+    }
+
+    // Finishes the task and wakes up the tasks waiting for it. A task that fails
+    // while nothing waits for it stops the script.
+    pub fn complete(
+        &self,
+        task: &Rc<TaskImpl>,
+        result: Result<LiteralValue, String>,
+    ) -> Result<(), String> {
+        *task.state.borrow_mut() = match &result {
+            Ok(value) => TaskState::Done(value.clone()),
+            Err(e) => TaskState::Failed(e.clone()),
+        };
+
+        let waiters = std::mem::take(&mut *task.waiters.borrow_mut());
+        if let Err(e) = &result {
+            if waiters.is_empty() {
+                return Err(format!("Task '{}' failed: {}", task.name, e));
+            }
+        }
+        let mut ready = self.ready.borrow_mut();
+        for waiter in waiters {
+            ready.push_back((waiter, result.clone()));
+        }
+        Ok(())
+    }
+}
+
+// A queue of values between tasks. Sending never waits, receive() is a task that
+// is done once there is a value, or with nil once the channel is closed.
+#[derive(Default)]
+pub struct Channel {
+    values: RefCell<VecDeque<LiteralValue>>,
+    receivers: RefCell<VecDeque<Rc<TaskImpl>>>,
+    closed: Cell<bool>,
+}
+
+impl Channel {
+    pub fn send(&self, event_loop: &EventLoop, value: LiteralValue) -> Result<(), String> {
+        if self.closed.get() {
+            return Err(format!(
+                "Cannot send {} on a closed channel",
+                value.to_string()
+            ));
+        }
+
+        let receiver = self.receivers.borrow_mut().pop_front();
+        match receiver {
+            Some(receiver) => event_loop.complete(&receiver, Ok(value)),
+            None => {
+                self.values.borrow_mut().push_back(value);
+                Ok(())
+            }
+        }
+    }
+
+    pub fn receive(&self) -> Rc<TaskImpl> {
+        let task = Rc::new(TaskImpl::new("receive".to_string(), TaskBody::External));
+        let value = self.values.borrow_mut().pop_front();
+        match value {
+            Some(value) => *task.state.borrow_mut() = TaskState::Done(value),
+            None if self.closed.get() => {
+                *task.state.borrow_mut() = TaskState::Done(LiteralValue::Nil)
+            }
+            None => self.receivers.borrow_mut().push_back(task.clone()),
+        }
+        task
+    }
+
+    // Values already sent can still be received
+    pub fn close(&self, event_loop: &EventLoop) -> Result<(), String> {
+        self.closed.set(true);
+        let receivers = std::mem::take(&mut *self.receivers.borrow_mut());
+        for receiver in receivers {
+            event_loop.complete(&receiver, Ok(LiteralValue::Nil))?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn timers_fire_in_order_of_deadline() {
+        let event_loop = EventLoop::new();
+        let late = event_loop.sleep(3);
+        let early = event_loop.sleep(1);
+        let same = event_loop.sleep(1);
+
+        assert!(event_loop.run_once().unwrap());
+        assert!(early.result().is_some() && same.result().is_none());
+        assert!(event_loop.run_once().unwrap());
+        assert!(same.result().is_some() && late.result().is_none());
+        assert!(event_loop.run_once().unwrap());
+        assert_eq!(late.result(), Some(Ok(LiteralValue::Nil)));
+        assert_eq!(event_loop.now.get(), 3);
+        assert!(!event_loop.run_once().unwrap());
+    }
+
+    #[test]
+    fn channel_hands_values_to_receivers_in_order() {
+        let event_loop = EventLoop::new();
+        let channel = Channel::default();
+        let first = channel.receive();
+        let second = channel.receive();
+
+        channel
+            .send(&event_loop, LiteralValue::Number(1.0))
+            .unwrap();
+        assert_eq!(first.result(), Some(Ok(LiteralValue::Number(1.0))));
+        assert_eq!(second.result(), None);
+
+        channel.close(&event_loop).unwrap();
+        assert_eq!(second.result(), Some(Ok(LiteralValue::Nil)));
+        assert!(channel.send(&event_loop, LiteralValue::Nil).is_err());
+    }
+}
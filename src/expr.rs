@@ -1,8 +1,10 @@
 use crate::environment::Environment;
+use crate::event_loop::TaskImpl;
+use crate::generator::{contains_yield, Coroutine, GeneratorImpl};
 use crate::interpreter::Interpreter;
 use crate::scanner;
 use crate::scanner::{Token, TokenType};
-use std::cell::RefCell;
+use std::cell::{Cell, RefCell};
 use std::cmp::{Eq, PartialEq};
 use std::collections::HashMap;
 use std::hash::{Hash, Hasher};
@@ -20,15 +22,287 @@ pub struct LoxFunctionImpl {
     pub name: String,
     pub arity: usize,
     pub parent_env: Environment,
-    pub params: Vec<Token>,
-    pub body: Vec<Box<Stmt>>,
+    pub params: Vec<Parameter>,
+    pub body: Rc<Vec<Box<Stmt>>>,
+    // `init` methods return `this`, also when called directly on an instance
+    pub is_initializer: bool,
+    // Declared without a body, calling it is an error
+    pub is_abstract: bool,
+    // The body yields, so calling the function makes a generator
+    pub is_generator: bool,
+    // Calling the function starts a task instead of running the body
+    pub is_async: bool,
 }
 
+impl LoxFunctionImpl {
+    pub fn min_arity(&self) -> usize {
+        self.params
+            .iter()
+            .filter(|p| p.default.is_none() && !p.is_rest)
+            .count()
+    }
+
+    // None when the function takes a rest parameter
+    pub fn max_arity(&self) -> Option<usize> {
+        if self.params.iter().any(|p| p.is_rest) {
+            None
+        } else {
+            Some(self.params.len())
+        }
+    }
+
+    pub fn arity_description(&self) -> String {
+        match (self.min_arity(), self.max_arity()) {
+            (min, None) => format!("at least {min}"),
+            (min, Some(max)) if min == max => format!("{min}"),
+            (min, Some(max)) => format!("{min} to {max}"),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Parameter {
+    pub name: Token,
+    pub default: Option<Expr>,
+    pub is_rest: bool,
+    // `(a, b)` in place of a name, destructuring the argument
+    pub pattern: Option<Pattern>,
+    // a: Number
+    pub annotation: Option<Token>,
+}
+
+impl Parameter {
+    pub fn new(name: Token) -> Self {
+        Self {
+            name,
+            default: None,
+            is_rest: false,
+            pattern: None,
+            annotation: None,
+        }
+    }
+}
+
+// A set of methods that classes mix in with `with`. Methods declared without a
+// body must be implemented by the class.
+pub struct LoxTraitImpl {
+    pub name: String,
+    pub methods: HashMap<String, LoxFunctionImpl>,
+}
+
+// `enum Shape { Circle(radius), Empty }`, whose members are reached with
+// `Shape.Circle`: constructors for variants with fields, values for the others
+pub struct LoxEnumImpl {
+    pub name: String,
+    pub members: HashMap<String, LiteralValue>,
+}
+
+pub struct LoxVariantImpl {
+    pub enum_name: String,
+    pub name: String,
+    pub fields: Vec<String>,
+}
+
+// Fields of an instance by name, kept in the order they were first set, which is
+// the order they are listed and serialized in
+#[derive(Default)]
+pub struct Fields {
+    names: Vec<String>,
+    values: HashMap<String, LiteralValue>,
+}
+
+impl Fields {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn get(&self, name: &str) -> Option<&LiteralValue> {
+        self.values.get(name)
+    }
+
+    pub fn contains_key(&self, name: &str) -> bool {
+        self.values.contains_key(name)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.names.is_empty()
+    }
+
+    pub fn insert(&mut self, name: String, value: LiteralValue) {
+        if !self.values.contains_key(&name) {
+            self.names.push(name.clone());
+        }
+        self.values.insert(name, value);
+    }
+
+    pub fn keys(&self) -> impl Iterator<Item = &String> {
+        self.names.iter()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&String, &LiteralValue)> {
+        self.names.iter().map(|name| (name, &self.values[name]))
+    }
+}
+
+impl FromIterator<(String, LiteralValue)> for Fields {
+    fn from_iter<I: IntoIterator<Item = (String, LiteralValue)>>(iter: I) -> Self {
+        let mut fields = Fields::new();
+        for (name, value) in iter {
+            fields.insert(name, value);
+        }
+        fields
+    }
+}
+
+// Classes are shared: every instance points at the same class object, so methods
+// added later are seen by existing instances too
+pub struct LoxClassImpl {
+    pub name: String,
+    pub methods: RefCell<HashMap<String, LoxFunctionImpl>>,
+    pub superclass: Option<Rc<LoxClassImpl>>,
+    pub traits: Vec<Rc<LoxTraitImpl>>,
+    // Bumped whenever a method is added to this class
+    version: Cell<usize>,
+    // Results of find_method, including misses, valid while the versions of the
+    // class and its superclasses add up to cache_version
+    cache: RefCell<HashMap<String, Option<LoxFunctionImpl>>>,
+    cache_version: Cell<usize>,
+}
+
+impl LoxClassImpl {
+    pub fn new(
+        name: String,
+        methods: HashMap<String, LoxFunctionImpl>,
+        superclass: Option<Rc<LoxClassImpl>>,
+        traits: Vec<Rc<LoxTraitImpl>>,
+    ) -> Self {
+        Self {
+            name,
+            methods: RefCell::new(methods),
+            superclass,
+            traits,
+            version: Cell::new(0),
+            cache: RefCell::new(HashMap::new()),
+            cache_version: Cell::new(0),
+        }
+    }
+
+    pub fn define_method(&self, name: String, method: LoxFunctionImpl) {
+        self.methods.borrow_mut().insert(name, method);
+        self.version.set(self.version.get() + 1);
+    }
+
+    // Changes when a method is added to the class or to any of its superclasses,
+    // since versions only go up
+    fn chain_version(&self) -> usize {
+        let inherited = match &self.superclass {
+            Some(superclass) => superclass.chain_version(),
+            None => 0,
+        };
+        self.version.get() + inherited
+    }
+
+    // Walks the superclass chain looking for the class itself, not just its name
+    pub fn inherits_from(&self, class: &LoxClassImpl) -> bool {
+        if std::ptr::eq(self, class) {
+            return true;
+        }
+        match &self.superclass {
+            Some(superclass) => superclass.inherits_from(class),
+            None => false,
+        }
+    }
+
+    // True if the class or one of its superclasses was declared `with` the trait
+    pub fn implements(&self, trait_: &LoxTraitImpl) -> bool {
+        if self.traits.iter().any(|t| std::ptr::eq(t.as_ref(), trait_)) {
+            return true;
+        }
+        match &self.superclass {
+            Some(superclass) => superclass.implements(trait_),
+            None => false,
+        }
+    }
+
+    // Names of all methods on the class and its superclasses
+    pub fn method_names(&self) -> Vec<String> {
+        let mut names: Vec<String> = self.methods.borrow().keys().cloned().collect();
+        if let Some(superclass) = &self.superclass {
+            for name in superclass.method_names() {
+                if !names.contains(&name) {
+                    names.push(name);
+                }
+            }
+        }
+        names.sort();
+        names
+    }
+
+    // Errors if a method is still abstract, or does not take the number of
+    // arguments a trait requires
+    pub fn check_implemented(&self) -> Result<(), String> {
+        for name in self.method_names() {
+            let method = self
+                .find_method(&name)
+                .expect("Method listed but not found");
+            if method.is_abstract {
+                return Err(format!(
+                    "Class '{}' must implement abstract method '{}'",
+                    self.name, name
+                ));
+            }
+        }
+
+        for trait_ in self.traits.iter() {
+            for (name, required) in trait_.methods.iter().filter(|(_, m)| m.is_abstract) {
+                let method = self.find_method(name).expect("Required method not found");
+                let takes = method.min_arity() <= required.arity
+                    && method.max_arity().is_none_or(|max| max >= required.arity);
+                if !takes {
+                    return Err(format!(
+                        "Method '{}' of class '{}' must take {} arguments to implement trait '{}'",
+                        name, self.name, required.arity, trait_.name
+                    ));
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn find_method(&self, name: &str) -> Option<LoxFunctionImpl> {
+        let version = self.chain_version();
+        if self.cache_version.get() != version {
+            self.cache.borrow_mut().clear();
+            self.cache_version.set(version);
+        }
+        if let Some(cached) = self.cache.borrow().get(name) {
+            return cached.clone();
+        }
+
+        let method = match self.methods.borrow().get(name) {
+            Some(fun) => Some(fun.clone()),
+            None => match &self.superclass {
+                Some(superclass) => superclass.find_method(name),
+                None => None,
+            },
+        };
+        self.cache
+            .borrow_mut()
+            .insert(name.to_string(), method.clone());
+        method
+    }
+}
+
+pub type NativeClosure = dyn Fn(&[LiteralValue]) -> Result<LiteralValue, String>;
+
 #[derive(Clone)]
 pub struct NativeFunctionImpl {
     pub name: String,
     pub arity: usize,
-    pub fun: Rc<dyn Fn(&Vec<LiteralValue>) -> LiteralValue>,
+    // Accepts `arity` or more arguments
+    pub variadic: bool,
+    pub fun: Rc<NativeClosure>,
 }
 
 #[derive(Clone)]
@@ -39,16 +313,33 @@ pub enum LiteralValue {
     False,
     Nil,
     Callable(CallableImpl),
-    LoxClass {
+    LoxClass(Rc<LoxClassImpl>),
+    LoxTrait(Rc<LoxTraitImpl>),
+    LoxInstance {
+        class: Rc<LoxClassImpl>,
+        fields: Rc<RefCell<Fields>>,
+        frozen: Rc<RefCell<bool>>,
+    },
+    Range {
+        start: f64,
+        end: f64,
+        inclusive: bool,
+    },
+    List(Rc<RefCell<Vec<LiteralValue>>>),
+    Tuple(Rc<Vec<LiteralValue>>),
+    Namespace {
         name: String,
-        methods: HashMap<String, LoxFunctionImpl>,
-        superclass: Option<Box<LiteralValue>>,
-        //methods: Vec<(String, LiteralValue)>, // TODO Could also add static fields?
+        members: Rc<HashMap<String, LiteralValue>>,
     },
-    LoxInstance {
-        class: Box<LiteralValue>,
-        fields: Rc<RefCell<Vec<(String, LiteralValue)>>>,
+    LoxEnum(Rc<LoxEnumImpl>),
+    // Constructor of a variant with fields
+    EnumVariant(Rc<LoxVariantImpl>),
+    EnumValue {
+        variant: Rc<LoxVariantImpl>,
+        values: Rc<Vec<LiteralValue>>,
     },
+    Generator(Rc<GeneratorImpl>),
+    Task(Rc<TaskImpl>),
 }
 use LiteralValue::*;
 
@@ -62,23 +353,44 @@ impl PartialEq for LiteralValue {
     fn eq(&self, other: &Self) -> bool {
         match (self, other) {
             (Number(x), Number(y)) => x == y,
+            // Functions, classes and instances are equal only to themselves
+            (Callable(LoxFunction(f)), Callable(LoxFunction(g))) => {
+                Rc::ptr_eq(&f.body, &g.body)
+                    && Rc::ptr_eq(&f.parent_env.values, &g.parent_env.values)
+            }
+            (Callable(NativeFunction(f)), Callable(NativeFunction(g))) => {
+                Rc::ptr_eq(&f.fun, &g.fun)
+            }
+            (LoxClass(x), LoxClass(y)) => Rc::ptr_eq(x, y),
+            (LoxTrait(x), LoxTrait(y)) => Rc::ptr_eq(x, y),
+            (Generator(x), Generator(y)) => Rc::ptr_eq(x, y),
+            (Task(x), Task(y)) => Rc::ptr_eq(x, y),
+            (LoxEnum(x), LoxEnum(y)) => Rc::ptr_eq(x, y),
+            (EnumVariant(x), EnumVariant(y)) => Rc::ptr_eq(x, y),
+            // Enum values are equal when they are the same variant with equal fields
             (
-                Callable(CallableImpl::LoxFunction(LoxFunctionImpl { name, arity, .. })),
-                Callable(CallableImpl::LoxFunction(LoxFunctionImpl {
-                    name: name2,
-                    arity: arity2,
-                    ..
-                })),
-            ) => name == name2 && arity == arity2,
-            (
-                Callable(CallableImpl::NativeFunction(NativeFunctionImpl { name, arity, .. })),
-                Callable(CallableImpl::NativeFunction(NativeFunctionImpl {
-                    name: name2,
-                    arity: arity2,
-                    ..
-                })),
-            ) => name == name2 && arity == arity2,
+                EnumValue { variant, values },
+                EnumValue {
+                    variant: variant2,
+                    values: values2,
+                },
+            ) => Rc::ptr_eq(variant, variant2) && values == values2,
+            (LoxInstance { fields: x, .. }, LoxInstance { fields: y, .. }) => Rc::ptr_eq(x, y),
             (StringValue(x), StringValue(y)) => x == y,
+            (
+                Range {
+                    start,
+                    end,
+                    inclusive,
+                },
+                Range {
+                    start: start2,
+                    end: end2,
+                    inclusive: inclusive2,
+                },
+            ) => start == start2 && end == end2 && inclusive == inclusive2,
+            (List(x), List(y)) => *x.borrow() == *y.borrow(),
+            (Tuple(x), Tuple(y)) => x == y,
             (True, True) => true,
             (False, False) => true,
             (Nil, Nil) => true,
@@ -101,21 +413,6 @@ fn unwrap_as_string(literal: Option<scanner::LiteralValue>) -> String {
     }
 }
 
-macro_rules! class_name {
-    ($class:expr) => {{
-        if let LiteralValue::LoxClass {
-            name,
-            methods: _,
-            superclass: _,
-        } = &**$class
-        {
-            name
-        } else {
-            panic!("Unreachable")
-        }
-    }};
-}
-
 impl LiteralValue {
     pub fn to_string(&self) -> String {
         match self {
@@ -134,14 +431,120 @@ impl LiteralValue {
                 arity,
                 ..
             })) => format!("{name}/{arity}"),
-            LiteralValue::LoxClass {
-                name,
-                methods: _,
-                superclass: _,
-            } => format!("Class '{name}'"),
-            LiteralValue::LoxInstance { class, fields: _ } => {
-                format!("Instance of '{}'", class_name!(class))
+            LiteralValue::LoxClass(class) => format!("Class '{}'", class.name),
+            LiteralValue::LoxTrait(trait_) => format!("Trait '{}'", trait_.name),
+            LiteralValue::Generator(generator) => format!("Generator '{}'", generator.name),
+            LiteralValue::Task(task) => format!("Task '{}'", task.name),
+            LiteralValue::LoxInstance { class, .. } => match call_special(self, "__str", vec![]) {
+                Some(Ok(value)) => value.to_raw_string(),
+                _ => format!("Instance of '{}'", class.name),
+            },
+            LiteralValue::Range {
+                start,
+                end,
+                inclusive,
+            } => {
+                if *inclusive {
+                    format!("{start}..={end}")
+                } else {
+                    format!("{start}..{end}")
+                }
+            }
+            LiteralValue::List(items) => format!(
+                "[{}]",
+                items
+                    .borrow()
+                    .iter()
+                    .map(|item| item.to_string())
+                    .collect::<Vec<String>>()
+                    .join(", ")
+            ),
+            LiteralValue::Tuple(items) if items.len() == 1 => {
+                format!("({},)", items[0].to_string())
+            }
+            LiteralValue::Tuple(items) => format!(
+                "({})",
+                items
+                    .iter()
+                    .map(|item| item.to_string())
+                    .collect::<Vec<String>>()
+                    .join(", ")
+            ),
+            LiteralValue::Namespace { name, members: _ } => format!("Module '{name}'"),
+            LiteralValue::LoxEnum(enum_) => format!("Enum '{}'", enum_.name),
+            LiteralValue::EnumVariant(variant) => {
+                format!("{}/{}", variant.name, variant.fields.len())
+            }
+            LiteralValue::EnumValue { variant, values } if values.is_empty() => {
+                variant.name.clone()
+            }
+            LiteralValue::EnumValue { variant, values } => format!(
+                "{}({})",
+                variant.name,
+                values
+                    .iter()
+                    .map(|value| value.to_string())
+                    .collect::<Vec<String>>()
+                    .join(", ")
+            ),
+        }
+    }
+
+    // Like to_string, but an error raised by a `__str` method is passed on
+    pub fn to_display_string(&self) -> Result<String, String> {
+        match self {
+            LiteralValue::LoxInstance { .. } => match call_special(self, "__str", vec![]) {
+                Some(result) => Ok(result?.to_raw_string()),
+                None => Ok(self.to_string()),
+            },
+            LiteralValue::List(items) => {
+                let mut parts = vec![];
+                for item in items.borrow().iter() {
+                    parts.push(item.to_display_string()?);
+                }
+                Ok(format!("[{}]", parts.join(", ")))
+            }
+            LiteralValue::Tuple(items) if items.len() == 1 => {
+                Ok(format!("({},)", items[0].to_display_string()?))
             }
+            LiteralValue::Tuple(items) => {
+                let mut parts = vec![];
+                for item in items.iter() {
+                    parts.push(item.to_display_string()?);
+                }
+                Ok(format!("({})", parts.join(", ")))
+            }
+            other => Ok(other.to_string()),
+        }
+    }
+
+    // Strings without quotes, what `__str` methods return
+    fn to_raw_string(&self) -> String {
+        match self {
+            LiteralValue::StringValue(s) => s.clone(),
+            other => other.to_string(),
+        }
+    }
+
+    // Identity for the `is` operator: lists, instances, classes and functions are
+    // only the same as themselves, other values when they are equal
+    pub fn is_same(&self, other: &LiteralValue) -> bool {
+        match (self, other) {
+            (List(x), List(y)) => Rc::ptr_eq(x, y),
+            (x, y) => x == y,
+        }
+    }
+
+    // A field of an instance or of an enum value
+    pub fn field(&self, name: &str) -> Option<LiteralValue> {
+        match self {
+            LoxInstance { fields, .. } => fields.borrow().get(name).cloned(),
+            EnumValue { variant, values } => variant
+                .fields
+                .iter()
+                .position(|field| field == name)
+                .map(|i| values[i].clone()),
+            _ => None,
         }
     }
 
@@ -153,12 +556,18 @@ impl LiteralValue {
             LiteralValue::False => "Boolean",
             LiteralValue::Nil => "nil",
             LiteralValue::Callable(_) => "Callable",
-            LiteralValue::LoxClass {
-                name: _,
-                methods: _,
-                superclass: _,
-            } => "Class",
-            LiteralValue::LoxInstance { class, fields: _ } => &class_name!(class),
+            LiteralValue::LoxClass(_) => "Class",
+            LiteralValue::LoxTrait(_) => "Trait",
+            LiteralValue::LoxInstance { class, .. } => &class.name,
+            LiteralValue::Range { .. } => "Range",
+            LiteralValue::List(_) => "List",
+            LiteralValue::Tuple(_) => "Tuple",
+            LiteralValue::Generator(_) => "Generator",
+            LiteralValue::Task(_) => "Task",
+            LiteralValue::Namespace { .. } => "Module",
+            LiteralValue::LoxEnum(_) => "Enum",
+            LiteralValue::EnumVariant(_) => "Callable",
+            LiteralValue::EnumValue { variant, .. } => &variant.enum_name,
         }
     }
 
@@ -197,11 +606,19 @@ impl LiteralValue {
                     False
                 }
             }
+            List(items) => {
+                if items.borrow().is_empty() {
+                    True
+                } else {
+                    False
+                }
+            }
+            Tuple(items) => LiteralValue::from_bool(items.is_empty()),
             True => False,
             False => True,
             Nil => True,
             Callable(_) => panic!("Cannot use Callable as a falsy value"),
-            LoxClass { .. } => panic!("Cannot use class as a falsy value"),
+            LoxClass(_) => panic!("Cannot use class as a falsy value"),
             _ => panic!("Not valid as a boolean value"),
         }
     }
@@ -222,11 +639,19 @@ impl LiteralValue {
                     True
                 }
             }
+            List(items) => {
+                if items.borrow().is_empty() {
+                    False
+                } else {
+                    True
+                }
+            }
+            Tuple(items) => LiteralValue::from_bool(!items.is_empty()),
             True => True,
             False => False,
             Nil => False,
             Callable(_) => panic!("Cannot use Callable as a truthy value"),
-            LoxClass { .. } => panic!("Cannot use class as a truthy value"),
+            LoxClass(_) => panic!("Cannot use class as a truthy value"),
             _ => panic!("Not valid as a boolean value"),
         }
     }
@@ -239,7 +664,7 @@ pub enum Expr {
     AnonFunction {
         id: usize,
         paren: Token,
-        arguments: Vec<Token>,
+        arguments: Vec<Parameter>,
         body: Vec<Box<Stmt>>,
     },
     Assign {
@@ -247,6 +672,18 @@ pub enum Expr {
         name: Token,
         value: Box<Expr>,
     },
+    // (a, b) = (b, a)
+    AssignTuple {
+        id: usize,
+        targets: Vec<Expr>,
+        value: Box<Expr>,
+    },
+    // await task
+    Await {
+        id: usize,
+        keyword: Token,
+        value: Box<Expr>,
+    },
     Binary {
         id: usize,
         left: Box<Expr>,
@@ -260,6 +697,8 @@ pub enum Expr {
         callee: Box<Expr>,
         paren: Token,
         arguments: Vec<Expr>,
+        // f(b: 3)
+        named_arguments: Vec<(Token, Expr)>,
     },
     Get {
         id: usize,
@@ -270,6 +709,16 @@ pub enum Expr {
         id: usize,
         expression: Box<Expr>,
     },
+    Index {
+        id: usize,
+        object: Box<Expr>,
+        bracket: Token,
+        index: Box<Expr>,
+    },
+    List {
+        id: usize,
+        elements: Vec<Expr>,
+    },
     Literal {
         id: usize,
         value: LiteralValue,
@@ -280,12 +729,25 @@ pub enum Expr {
         operator: Token,
         right: Box<Expr>,
     },
+    Match {
+        id: usize,
+        keyword: Token,
+        subject: Box<Expr>,
+        arms: Vec<MatchArm>,
+    },
     Set {
         id: usize,
         object: Box<Expr>,
         name: Token,
         value: Box<Expr>,
     },
+    SetIndex {
+        id: usize,
+        object: Box<Expr>,
+        bracket: Token,
+        index: Box<Expr>,
+        value: Box<Expr>,
+    },
     This {
         id: usize,
         keyword: Token,
@@ -295,6 +757,11 @@ pub enum Expr {
         keyword: Token,
         method: Token,
     },
+    // (1, "a")
+    Tuple {
+        id: usize,
+        elements: Vec<Expr>,
+    },
     Unary {
         id: usize,
         operator: Token,
@@ -306,6 +773,109 @@ pub enum Expr {
     },
 }
 
+#[derive(Clone)]
+pub struct MatchArm {
+    pub pattern: Pattern,
+    pub guard: Option<Expr>,
+    pub body: Expr,
+}
+
+#[derive(Debug, Clone)]
+pub enum Pattern {
+    // _
+    Wildcard,
+    // 1, "a", true, nil
+    Literal(LiteralValue),
+    // n
+    Binding(Token),
+    // "a" | "b"
+    Alternatives(Vec<Pattern>),
+    // Point { x, y: 0 }
+    Instance {
+        class: Token,
+        fields: Vec<(Token, Pattern)>,
+    },
+    // (q, r)
+    Tuple(Vec<Pattern>),
+}
+
+impl Pattern {
+    // Is true when the pattern is irrefutable, i.e. it matches any value
+    pub fn is_catch_all(&self) -> bool {
+        match self {
+            Pattern::Wildcard | Pattern::Binding(_) => true,
+            Pattern::Alternatives(alternatives) => alternatives.iter().any(|p| p.is_catch_all()),
+            Pattern::Literal(_) | Pattern::Instance { .. } | Pattern::Tuple(_) => false,
+        }
+    }
+
+    // Names introduced by the pattern, in order of appearance
+    pub fn bindings(&self) -> Vec<&Token> {
+        match self {
+            Pattern::Wildcard | Pattern::Literal(_) => vec![],
+            Pattern::Binding(name) => vec![name],
+            Pattern::Alternatives(alternatives) => {
+                alternatives.iter().flat_map(|p| p.bindings()).collect()
+            }
+            Pattern::Instance { class: _, fields } => {
+                fields.iter().flat_map(|(_, p)| p.bindings()).collect()
+            }
+            Pattern::Tuple(patterns) => patterns.iter().flat_map(|p| p.bindings()).collect(),
+        }
+    }
+
+    // Checks the value against the pattern, binding names into the environment on the way
+    pub fn matches(&self, value: &LiteralValue, environment: &Environment) -> bool {
+        match self {
+            Pattern::Wildcard => true,
+            Pattern::Literal(literal) => literal == value,
+            Pattern::Binding(name) => {
+                environment.define(name.lexeme.clone(), value.clone());
+                true
+            }
+            Pattern::Alternatives(alternatives) => {
+                alternatives.iter().any(|p| p.matches(value, environment))
+            }
+            Pattern::Instance { class, fields } => {
+                // Instances of the class or a subclass, or values of an enum variant. The
+                // class is the one the name refers to here, another class of the same
+                // name does not match
+                let matches_type = match value {
+                    LoxInstance {
+                        class: instance_class,
+                        ..
+                    } => match environment.lookup(&class.lexeme) {
+                        Some(LoxClass(pattern_class)) => {
+                            instance_class.inherits_from(&pattern_class)
+                        }
+                        _ => false,
+                    },
+                    EnumValue { variant, .. } => variant.name == class.lexeme,
+                    _ => false,
+                };
+                if !matches_type {
+                    return false;
+                }
+
+                for (field_name, pattern) in fields {
+                    match value.field(&field_name.lexeme) {
+                        Some(field_value) if pattern.matches(&field_value, environment) => (),
+                        _ => return false,
+                    }
+                }
+                true
+            }
+            Pattern::Tuple(patterns) => match value {
+                Tuple(values) if values.len() == patterns.len() => patterns
+                    .iter()
+                    .zip(values.iter())
+                    .all(|(pattern, value)| pattern.matches(value, environment)),
+                _ => false,
+            },
+        }
+    }
+}
+
 impl std::fmt::Debug for Expr {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "{}: {}", self.get_id(), self.to_string())
@@ -342,6 +912,16 @@ impl Expr {
                 name: _,
                 value: _,
             } => *id,
+            Expr::AssignTuple {
+                id,
+                targets: _,
+                value: _,
+            } => *id,
+            Expr::Await {
+                id,
+                keyword: _,
+                value: _,
+            } => *id,
             Expr::Binary {
                 id,
                 left: _,
@@ -354,6 +934,7 @@ impl Expr {
                 callee: _,
                 paren: _,
                 arguments: _,
+                named_arguments: _,
             } => *id,
             Expr::Get {
                 id,
@@ -361,6 +942,13 @@ impl Expr {
                 name: _,
             } => *id,
             Expr::Grouping { id, expression: _ } => *id,
+            Expr::Index {
+                id,
+                object: _,
+                bracket: _,
+                index: _,
+            } => *id,
+            Expr::List { id, elements: _ } => *id,
             Expr::Literal { id, value: _ } => *id,
             Expr::Logical {
                 id,
@@ -368,18 +956,32 @@ impl Expr {
                 operator: _,
                 right: _,
             } => *id,
+            Expr::Match {
+                id,
+                keyword: _,
+                subject: _,
+                arms: _,
+            } => *id,
             Expr::Set {
                 id,
                 object: _,
                 name: _,
                 value: _,
             } => *id,
+            Expr::SetIndex {
+                id,
+                object: _,
+                bracket: _,
+                index: _,
+                value: _,
+            } => *id,
             Expr::This { id, keyword: _ } => *id,
             Expr::Super {
                 id,
                 keyword: _,
                 method: _,
             } => *id,
+            Expr::Tuple { id, elements: _ } => *id,
             Expr::Unary {
                 id,
                 operator: _,
@@ -401,6 +1003,24 @@ impl Expr {
                 body: _,
             } => format!("anon/{}", arguments.len()),
             Expr::Assign { id: _, name, value } => format!("({name:?} = {}", value.to_string()),
+            Expr::AssignTuple {
+                id: _,
+                targets,
+                value,
+            } => format!(
+                "((tuple {}) = {})",
+                targets
+                    .iter()
+                    .map(|e| e.to_string())
+                    .collect::<Vec<String>>()
+                    .join(" "),
+                value.to_string()
+            ),
+            Expr::Await {
+                id: _,
+                keyword: _,
+                value,
+            } => format!("(await {})", value.to_string()),
             Expr::Binary {
                 id: _,
                 left,
@@ -417,6 +1037,7 @@ impl Expr {
                 callee,
                 paren: _,
                 arguments,
+                named_arguments: _,
             } => format!("({} {:?})", (*callee).to_string(), arguments),
             Expr::Get {
                 id: _,
@@ -426,6 +1047,20 @@ impl Expr {
             Expr::Grouping { id: _, expression } => {
                 format!("(group {})", (*expression).to_string())
             }
+            Expr::Index {
+                id: _,
+                object,
+                bracket: _,
+                index,
+            } => format!("(index {} {})", object.to_string(), index.to_string()),
+            Expr::List { id: _, elements } => format!(
+                "(list {})",
+                elements
+                    .iter()
+                    .map(|e| e.to_string())
+                    .collect::<Vec<String>>()
+                    .join(" ")
+            ),
             Expr::Literal { id: _, value } => format!("{}", value.to_string()),
             Expr::Logical {
                 id: _,
@@ -438,6 +1073,19 @@ impl Expr {
                 left.to_string(),
                 right.to_string()
             ),
+            Expr::Match {
+                id: _,
+                keyword: _,
+                subject,
+                arms,
+            } => format!(
+                "(match {} {})",
+                subject.to_string(),
+                arms.iter()
+                    .map(|arm| format!("(arm {})", arm.body.to_string()))
+                    .collect::<Vec<String>>()
+                    .join(" ")
+            ),
             Expr::Set {
                 id: _,
                 object,
@@ -449,12 +1097,32 @@ impl Expr {
                 name.to_string(),
                 value.to_string()
             ),
+            Expr::SetIndex {
+                id: _,
+                object,
+                bracket: _,
+                index,
+                value,
+            } => format!(
+                "(setindex {} {} {})",
+                object.to_string(),
+                index.to_string(),
+                value.to_string()
+            ),
             Expr::This { id: _, keyword: _ } => format!("(this)"),
             Expr::Super {
                 id: _,
                 keyword: _,
                 method,
             } => format!("(super {})", method.lexeme),
+            Expr::Tuple { id: _, elements } => format!(
+                "(tuple {})",
+                elements
+                    .iter()
+                    .map(|e| e.to_string())
+                    .collect::<Vec<String>>()
+                    .join(" ")
+            ),
             Expr::Unary {
                 id: _,
                 operator,
@@ -477,8 +1145,8 @@ impl Expr {
                 body,
             } => {
                 // We have to clone everything so the borrow checker doesnt get scared about us taking ownership of the values in the Expr
-                let arity = arguments.len();
-                let arguments: Vec<Token> = arguments.iter().map(|t| (*t).clone()).collect();
+                let arity = arguments.iter().filter(|p| !p.is_rest).count();
+                let arguments: Vec<Parameter> = arguments.iter().map(|t| (*t).clone()).collect();
                 let body: Vec<Box<Stmt>> = body.iter().map(|b| (*b).clone()).collect();
 
                 let callable_impl = CallableImpl::LoxFunction(LoxFunctionImpl {
@@ -486,21 +1154,42 @@ impl Expr {
                     arity,
                     parent_env: environment.clone(),
                     params: arguments,
-                    body,
+                    is_generator: contains_yield(&body),
+                    body: Rc::new(body),
+                    is_initializer: false,
+                    is_abstract: false,
+                    is_async: false,
                 });
 
                 Ok(Callable(callable_impl))
             }
             Expr::Assign { id: _, name, value } => {
                 let new_value = (*value).evaluate(environment.clone())?;
-                let assign_success =
-                    environment.assign(&name.lexeme, new_value.clone(), self.get_id());
+                environment.assign(&name.lexeme, new_value.clone(), self.get_id())?;
 
-                if assign_success {
-                    Ok(new_value)
-                } else {
-                    Err(format!("Variable {} has not been declared", name.lexeme))
-                }
+                Ok(new_value)
+            }
+            Expr::AssignTuple {
+                id: _,
+                targets,
+                value,
+            } => {
+                // The whole right-hand side is evaluated first, so `(a, b) = (b, a)` swaps
+                let new_value = value.evaluate(environment.clone())?;
+                assign_tuple(targets, &new_value, &environment)?;
+
+                Ok(new_value)
+            }
+            Expr::Await {
+                id: _,
+                keyword: _,
+                value,
+            } => {
+                // A task suspends at its awaits (see Coroutine), which the resolver
+                // only allows at the start of a statement. At the top level the event
+                // loop runs until the value is ready.
+                let value = value.evaluate(environment.clone())?;
+                environment.runtime.event_loop.block_on(value)
             }
             Expr::Variable { id: _, name } => match environment.get(&name.lexeme, self.get_id()) {
                 Some(value) => Ok(value.clone()),
@@ -515,59 +1204,94 @@ impl Expr {
                 callee,
                 paren: _,
                 arguments,
+                named_arguments,
             } => {
                 // Look up function definition in environment
-                // let callable_distance = locals.borrow().get(&self.get_id());
                 let callable: LiteralValue = (*callee).evaluate(environment.clone())?;
-                let callable_clone = callable.clone();
-                match callable {
-                    Callable(CallableImpl::LoxFunction(loxfun)) => {
-                        run_lox_function(loxfun, arguments, environment)
-                    }
-                    Callable(CallableImpl::NativeFunction(nativefun)) => {
-                        let mut evaluated_arguments = vec![];
-                        for argument in arguments {
-                            evaluated_arguments.push(argument.evaluate(environment.clone())?);
-                        }
-                        Ok((nativefun.fun)(&evaluated_arguments))
-                    }
-                    LoxClass {
-                        name: _,
-                        methods,
-                        superclass: _,
-                    } => {
-                        let instance = LoxInstance {
-                            class: Box::new(callable_clone.clone()),
-                            fields: Rc::new(RefCell::new(vec![])),
-                        };
 
-                        // Call constructor if present
-                        if let Some(init_method) = methods.get("init") {
-                            if init_method.arity != arguments.len() {
-                                return Err(
-                                    "Invalid number of arguments in constructor".to_string()
-                                );
-                            }
-
-                            // let new_env = environment.enclose();
-                            // new_env.define("this".to_string(), instance.clone());
-                            // let mut init_method = init_method.clone();
-                            // init_method.parent_env = new_env.clone();
-                            let mut init_method = init_method.clone();
-                            init_method.parent_env = init_method.parent_env.enclose();
-                            init_method
-                                .parent_env
-                                .define("this".to_string(), instance.clone());
-
-                            if let Err(msg) = run_lox_function(init_method, arguments, environment)
-                            {
-                                return Err(msg);
-                            }
-                        }
+                let mut evaluated_arguments = vec![];
+                for argument in arguments {
+                    evaluated_arguments.push(argument.evaluate(environment.clone())?);
+                }
+                let mut evaluated_named_arguments = vec![];
+                for (name, argument) in named_arguments {
+                    let value = argument.evaluate(environment.clone())?;
+                    evaluated_named_arguments.push((name.lexeme.clone(), value));
+                }
 
-                        Ok(instance)
+                call_value(callable, evaluated_arguments, evaluated_named_arguments)
+            }
+            Expr::List { id: _, elements } => {
+                let mut items = vec![];
+                for element in elements {
+                    items.push(element.evaluate(environment.clone())?);
+                }
+                Ok(List(Rc::new(RefCell::new(items))))
+            }
+            Expr::Tuple { id: _, elements } => {
+                let mut items = vec![];
+                for element in elements {
+                    items.push(element.evaluate(environment.clone())?);
+                }
+                Ok(Tuple(Rc::new(items)))
+            }
+            Expr::Index {
+                id: _,
+                object,
+                bracket,
+                index,
+            } => {
+                let obj_value = object.evaluate(environment.clone())?;
+                let index = index.evaluate(environment.clone())?;
+                match &obj_value {
+                    List(items) => {
+                        let i = list_index(&index, items.borrow().len())?;
+                        Ok(items.borrow()[i].clone())
+                    }
+                    Tuple(items) => {
+                        let i = list_index(&index, items.len())?;
+                        Ok(items[i].clone())
+                    }
+                    StringValue(s) => {
+                        let chars: Vec<char> = s.chars().collect();
+                        let i = list_index(&index, chars.len())?;
+                        Ok(StringValue(chars[i].to_string()))
+                    }
+                    LoxInstance { .. } if bind_method(&obj_value, "__index").is_some() => {
+                        call_special(&obj_value, "__index", vec![index]).unwrap()
                     }
-                    other => Err(format!("{} is not callable", other.to_type())),
+                    other => Err(format!(
+                        "Line {}: Cannot index into type {}",
+                        bracket.line_number,
+                        other.to_type()
+                    )),
+                }
+            }
+            Expr::SetIndex {
+                id: _,
+                object,
+                bracket,
+                index,
+                value,
+            } => {
+                let obj_value = object.evaluate(environment.clone())?;
+                let index = index.evaluate(environment.clone())?;
+                let value = value.evaluate(environment.clone())?;
+                if let List(items) = &obj_value {
+                    let i = list_index(&index, items.borrow().len())?;
+                    items.borrow_mut()[i] = value.clone();
+                    Ok(value)
+                } else if let Some(result) =
+                    call_special(&obj_value, "__setindex", vec![index, value.clone()])
+                {
+                    result?;
+                    Ok(value)
+                } else {
+                    Err(format!(
+                        "Line {}: Cannot set index on type {}",
+                        bracket.line_number,
+                        obj_value.to_type()
+                    ))
                 }
             }
             Expr::Literal { id: _, value } => Ok((*value).clone()),
@@ -603,33 +1327,59 @@ impl Expr {
                 name,
             } => {
                 let obj_value = object.evaluate(environment.clone())?;
-                // Now obj_value should be a LoxInstance
-                if let LoxInstance { class, fields } = obj_value.clone() {
-                    for (field_name, value) in (*fields.borrow()).iter() {
-                        // Are we getting a field on the object?
-                        if field_name == &name.lexeme {
-                            return Ok(value.clone());
+                if let Namespace {
+                    name: namespace,
+                    members,
+                } = &obj_value
+                {
+                    return match members.get(&name.lexeme) {
+                        Some(member) => Ok(member.clone()),
+                        None => Err(format!(
+                            "Module {} has no member named {}",
+                            namespace, name.lexeme
+                        )),
+                    };
+                }
+
+                match &obj_value {
+                    LoxEnum(enum_) => {
+                        return match enum_.members.get(&name.lexeme) {
+                            Some(member) => Ok(member.clone()),
+                            None => Err(format!(
+                                "Enum {} has no variant named {}",
+                                enum_.name, name.lexeme
+                            )),
                         }
                     }
+                    EnumValue { variant, .. } => {
+                        return obj_value.field(&name.lexeme).ok_or_else(|| {
+                            format!(
+                                "Variant {} has no field named {}",
+                                variant.name, name.lexeme
+                            )
+                        })
+                    }
+                    Generator(generator) if name.lexeme == "next" => {
+                        return Ok(generator_next(generator.clone()))
+                    }
+                    Generator(generator) => {
+                        return Err(format!(
+                            "Generator {} has no method named {}",
+                            generator.name, name.lexeme
+                        ))
+                    }
+                    _ => (),
+                }
+
+                // Now obj_value should be a LoxInstance
+                if let LoxInstance { fields, .. } = &obj_value {
+                    // Are we getting a field on the object?
+                    if let Some(value) = fields.borrow().get(&name.lexeme) {
+                        return Ok(value.clone());
+                    }
                     // Are we getting a method on the object?
-                    // TODO Make a function that finds a method on a class by looking first at the
-                    // class, then at the superclasses in a recursive manner
-
-                    if let LoxClass {
-                        name: _,
-                        methods: _,
-                        superclass: _,
-                    } = class.as_ref()
-                    {
-                        if let Some(method) = find_method(&name.lexeme, *class.clone()) {
-                            let mut callable_impl = method.clone();
-                            let new_env = callable_impl.parent_env.enclose();
-                            new_env.define("this".to_string(), obj_value.clone());
-                            callable_impl.parent_env = new_env;
-                            return Ok(Callable(LoxFunction(callable_impl)));
-                        }
-                    } else {
-                        panic!("The class field on an instance was not a LoxClass");
+                    if let Some(method) = bind_method(&obj_value, &name.lexeme) {
+                        return Ok(Callable(LoxFunction(method)));
                     }
                     Err(format!("No field named {} on this instance", name.lexeme))
                 } else {
@@ -639,6 +1389,30 @@ impl Expr {
                     ))
                 }
             }
+            Expr::Match {
+                id: _,
+                keyword: _,
+                subject,
+                arms,
+            } => {
+                let value = subject.evaluate(environment.clone())?;
+                for arm in arms {
+                    // Every arm gets its own scope for the names bound by its pattern
+                    let arm_env = environment.enclose();
+                    if !arm.pattern.matches(&value, &arm_env) {
+                        continue;
+                    }
+                    if let Some(guard) = &arm.guard {
+                        if guard.evaluate(arm_env.clone())?.is_truthy() != True {
+                            continue;
+                        }
+                    }
+
+                    return arm.body.evaluate(arm_env);
+                }
+
+                Err(format!("No match arm matched value {}", value.to_string()))
+            }
             Expr::Set {
                 id: _,
                 object, //object.name = value
@@ -646,27 +1420,37 @@ impl Expr {
                 value,
             } => {
                 let obj_value = object.evaluate(environment.clone())?;
-                if let LoxInstance { class: _, fields } = obj_value {
+                if let LoxInstance {
+                    class,
+                    fields,
+                    frozen,
+                } = obj_value
+                {
+                    if *frozen.borrow() {
+                        return Err(format!(
+                            "Cannot set property {} on frozen instance of '{}'",
+                            name.lexeme, class.name
+                        ));
+                    }
                     let value = value.evaluate(environment.clone())?;
+                    fields.borrow_mut().insert(name.lexeme.clone(), value);
 
-                    let mut idx = 0;
-                    let mut found = false;
-                    for i in 0..(*fields.borrow()).len() {
-                        let field_name = &(*fields.borrow())[i].0;
-                        if field_name == &name.lexeme {
-                            idx = i;
-                            found = true;
-                            break;
+                    Ok(Nil)
+                } else if let LoxClass(class) = obj_value {
+                    // `Class.name = fun ...` adds a method, also for existing instances
+                    match value.evaluate(environment.clone())? {
+                        Callable(LoxFunction(mut method)) => {
+                            method.name = name.lexeme.clone();
+                            method.is_initializer = name.lexeme == "init";
+                            class.define_method(name.lexeme.clone(), method);
+                            Ok(Nil)
                         }
+                        other => Err(format!(
+                            "Can only add functions to class '{}', not {}",
+                            class.name,
+                            other.to_type()
+                        )),
                     }
-
-                    if found {
-                        (*fields.borrow_mut())[idx].1 = value.clone();
-                    } else {
-                        (*fields.borrow_mut()).push((name.lexeme.clone(), value));
-                    }
-
-                    Ok(Nil)
                 } else {
                     Err(format!(
                         "Cannot set property on type {}",
@@ -695,14 +1479,8 @@ impl Expr {
                 // let new_env = environment.enclose();
                 // new_env.define("this".to_string(), instance.clone());
 
-                if let LoxClass {
-                    name: _,
-                    methods,
-                    superclass: _,
-                } = superclass.clone()
-                {
-                    if let Some(method_value) = methods.get(&method.lexeme) {
-                        let mut method = method_value.clone();
+                if let LoxClass(superclass) = &superclass {
+                    if let Some(mut method) = superclass.find_method(&method.lexeme) {
                         method.parent_env = method.parent_env.enclose();
                         method
                             .parent_env
@@ -711,12 +1489,12 @@ impl Expr {
                     } else {
                         Err(format!(
                             "No method named {} on superclass {}",
-                            method.lexeme,
-                            superclass.to_type()
+                            method.lexeme, superclass.name
                         ))
                     }
                 } else {
-                    panic!("The superclass field on an instance was not a LoxClass");
+                    // Only methods added by `extend` to a class without a superclass
+                    Err("Class has no superclass".to_string())
                 }
             }
             Expr::Grouping { id: _, expression } => expression.evaluate(environment),
@@ -727,6 +1505,12 @@ impl Expr {
             } => {
                 let right = right.evaluate(environment)?;
 
+                if operator.token_type == TokenType::Minus {
+                    if let Some(result) = call_special(&right, "__neg", vec![]) {
+                        return result;
+                    }
+                }
+
                 match (&right, operator.token_type) {
                     (Number(x), TokenType::Minus) => Ok(Number(-x)),
                     (_, TokenType::Minus) => {
@@ -745,6 +1529,16 @@ impl Expr {
                 let left = left.evaluate(environment.clone())?;
                 let right = right.evaluate(environment.clone())?;
 
+                // `a + b` on an instance is `a.__add(b)`, `a != b` is `!a.__eq(b)`
+                if let Some(method) = operator_method(operator.token_type) {
+                    if let Some(result) = call_special(&left, method, vec![right.clone()]) {
+                        return match operator.token_type {
+                            TokenType::BangEqual => Ok(result?.is_falsy()),
+                            _ => result,
+                        };
+                    }
+                }
+
                 match (&left, operator.token_type, &right) {
                     (Number(x), TokenType::Plus, Number(y)) => Ok(Number(x + y)),
                     (Number(x), TokenType::Minus, Number(y)) => Ok(Number(x - y)),
@@ -761,6 +1555,17 @@ impl Expr {
                         Ok(LiteralValue::from_bool(x <= y))
                     }
 
+                    (Number(x), TokenType::DotDot, Number(y)) => Ok(Range {
+                        start: *x,
+                        end: *y,
+                        inclusive: false,
+                    }),
+                    (Number(x), TokenType::DotDotEqual, Number(y)) => Ok(Range {
+                        start: *x,
+                        end: *y,
+                        inclusive: true,
+                    }),
+
                     (StringValue(_), op, Number(_)) => {
                         Err(format!("{} is not defined for string and number", op))
                     }
@@ -772,6 +1577,7 @@ impl Expr {
                         Ok(StringValue(format!("{}{}", s1, s2)))
                     }
 
+                    (x, TokenType::Is, y) => Ok(LiteralValue::from_bool(x.is_same(y))),
                     (x, TokenType::BangEqual, y) => Ok(LiteralValue::from_bool(x != y)),
                     (x, TokenType::EqualEqual, y) => Ok(LiteralValue::from_bool(x == y)),
                     (StringValue(s1), TokenType::Greater, StringValue(s2)) => {
@@ -787,8 +1593,10 @@ impl Expr {
                         Ok(LiteralValue::from_bool(s1 <= s2))
                     }
                     (x, ttype, y) => Err(format!(
-                        "{} is not implemented for operands {:?} and {:?}",
-                        ttype, x, y
+                        "{} is not implemented for operands {} and {}",
+                        ttype,
+                        x.to_type(),
+                        y.to_type()
                     )),
                 }
             }
@@ -801,34 +1609,220 @@ impl Expr {
     }
 }
 
+pub fn call_value(
+    callable: LiteralValue,
+    arguments: Vec<LiteralValue>,
+    named_arguments: Vec<(String, LiteralValue)>,
+) -> Result<LiteralValue, String> {
+    match callable {
+        Callable(CallableImpl::LoxFunction(loxfun)) => {
+            run_lox_function(loxfun, arguments, named_arguments)
+        }
+        Callable(CallableImpl::NativeFunction(nativefun)) => {
+            if !named_arguments.is_empty() {
+                return Err(format!(
+                    "Callable {} does not take named arguments",
+                    nativefun.name
+                ));
+            }
+            if nativefun.variadic && arguments.len() < nativefun.arity {
+                return Err(format!(
+                    "Callable {} expected at least {} arguments but got {}",
+                    nativefun.name,
+                    nativefun.arity,
+                    arguments.len()
+                ));
+            }
+            if !nativefun.variadic && arguments.len() != nativefun.arity {
+                return Err(format!(
+                    "Callable {} expected {} arguments but got {}",
+                    nativefun.name,
+                    nativefun.arity,
+                    arguments.len()
+                ));
+            }
+
+            (nativefun.fun)(&arguments)
+        }
+        LoxClass(class) => {
+            let instance = LoxInstance {
+                class: class.clone(),
+                fields: Rc::new(RefCell::new(Fields::new())),
+                frozen: Rc::new(RefCell::new(false)),
+            };
+
+            // Call the constructor, which may be inherited from a superclass
+            let given = arguments.len() + named_arguments.len();
+            match bind_method(&instance, "init") {
+                Some(init_method) => {
+                    let too_many = match init_method.max_arity() {
+                        Some(max) => arguments.len() > max,
+                        None => false,
+                    };
+                    if too_many || given < init_method.min_arity() {
+                        return Err(format!(
+                            "Constructor of '{}' expected {} arguments but got {}",
+                            class.name,
+                            init_method.arity_description(),
+                            given
+                        ));
+                    }
+
+                    run_lox_function(init_method, arguments, named_arguments)
+                }
+                None if given > 0 => Err(format!(
+                    "Constructor of '{}' expected 0 arguments but got {}",
+                    class.name, given
+                )),
+                None => Ok(instance),
+            }
+        }
+        EnumVariant(variant) => {
+            if !named_arguments.is_empty() {
+                return Err(format!(
+                    "Variant {} does not take named arguments",
+                    variant.name
+                ));
+            }
+            if arguments.len() != variant.fields.len() {
+                return Err(format!(
+                    "Variant {} expected {} arguments but got {}",
+                    variant.name,
+                    variant.fields.len(),
+                    arguments.len()
+                ));
+            }
+
+            Ok(EnumValue {
+                variant,
+                values: Rc::new(arguments),
+            })
+        }
+        other => Err(format!("{} is not callable", other.to_type())),
+    }
+}
+
 pub fn run_lox_function(
     loxfun: LoxFunctionImpl,
-    arguments: &Vec<Expr>,
-    eval_env: Environment,
+    arguments: Vec<LiteralValue>,
+    named_arguments: Vec<(String, LiteralValue)>,
 ) -> Result<LiteralValue, String> {
+    if loxfun.is_abstract {
+        return Err(format!("Cannot call abstract method '{}'", loxfun.name));
+    }
+
     // Do some checking (correct number of args?)
-    if arguments.len() != loxfun.arity {
-        return Err(format!(
+    let given = arguments.len() + named_arguments.len();
+    let arity_error = || {
+        format!(
             "Callable {} expected {} arguments but got {}",
             loxfun.name,
-            loxfun.arity,
-            arguments.len()
+            loxfun.arity_description(),
+            given
+        )
+    };
+    if let Some(max) = loxfun.max_arity() {
+        if arguments.len() > max {
+            return Err(arity_error());
+        }
+    }
+    for (name, _) in named_arguments.iter() {
+        if !loxfun
+            .params
+            .iter()
+            .any(|p| !p.is_rest && &p.name.lexeme == name)
+        {
+            return Err(format!(
+                "Callable {} has no parameter named '{}'",
+                loxfun.name, name
+            ));
+        }
+    }
+
+    let fun_env = loxfun.parent_env.enclose();
+
+    // Bind parameters in order, so that defaults can refer to earlier parameters
+    let mut positional = arguments.into_iter();
+    let mut named_arguments = named_arguments;
+    for param in loxfun.params.iter() {
+        let value = if param.is_rest {
+            List(Rc::new(RefCell::new(positional.by_ref().collect())))
+        } else {
+            let named_value = named_arguments
+                .iter()
+                .position(|(name, _)| name == &param.name.lexeme)
+                .map(|i| named_arguments.remove(i).1);
+
+            match (positional.next(), named_value, &param.default) {
+                (Some(_), Some(_), _) => {
+                    return Err(format!(
+                        "Callable {} got multiple values for argument '{}'",
+                        loxfun.name, param.name.lexeme
+                    ))
+                }
+                (Some(value), None, _) | (None, Some(value), _) => value,
+                (None, None, Some(default)) => default.evaluate(fun_env.clone())?,
+                (None, None, None) => return Err(arity_error()),
+            }
+        };
+        if let Some(pattern) = &param.pattern {
+            if !pattern.matches(&value, &fun_env) {
+                return Err(format!(
+                    "Callable {} cannot destructure argument {}",
+                    loxfun.name,
+                    value.to_string()
+                ));
+            }
+        }
+        fun_env.define(param.name.lexeme.clone(), value);
+    }
+    if let Some((name, _)) = named_arguments.first() {
+        return Err(format!(
+            "Callable {} got multiple values for argument '{}'",
+            loxfun.name, name
         ));
     }
 
-    // Evaluate arguments
-    let mut arg_vals = vec![];
-    for arg in arguments {
-        let val = arg.evaluate(eval_env.clone())?;
-        arg_vals.push(val);
+    // The body runs later, a bit on every call to next()
+    if loxfun.is_generator {
+        let generator = GeneratorImpl::new(loxfun.name, loxfun.body, fun_env);
+        return Ok(Generator(Rc::new(generator)));
     }
 
-    let fun_env = loxfun.parent_env.enclose();
+    // The body runs as a task on the event loop, the caller awaits it for the result
+    if loxfun.is_async {
+        let coroutine = Coroutine::new(&loxfun.name, loxfun.body, fun_env.clone());
+        let task = Rc::new(TaskImpl::coroutine(loxfun.name, coroutine));
+        fun_env.runtime.event_loop.spawn(task.clone());
+        return Ok(Task(task));
+    }
 
-    for (i, val) in arg_vals.iter().enumerate() {
-        fun_env.define(loxfun.params[i].lexeme.clone(), (*val).clone());
+    let runtime = fun_env.runtime.clone();
+    runtime.enter_call(&loxfun.name)?;
+    let result = run_body(&loxfun, fun_env);
+    runtime.exit_call();
+
+    if loxfun.is_initializer {
+        result?;
+        let this = loxfun.parent_env.values.borrow().get("this").cloned();
+        return Ok(this.unwrap_or(Nil));
     }
+    result
+}
+
+// The next() method of a generator, which returns nil once it has finished
+fn generator_next(generator: Rc<GeneratorImpl>) -> LiteralValue {
+    Callable(NativeFunction(NativeFunctionImpl {
+        name: "next".to_string(),
+        arity: 0,
+        variadic: false,
+        fun: Rc::new(move |_args: &[LiteralValue]| {
+            Ok(generator.resume()?.unwrap_or(LiteralValue::Nil))
+        }),
+    }))
+}
 
+fn run_body(loxfun: &LoxFunctionImpl, fun_env: Environment) -> Result<LiteralValue, String> {
     let mut int = Interpreter::with_env(fun_env);
     for i in 0..(loxfun.body.len()) {
         let result = int.interpret(vec![&loxfun.body[i]]);
@@ -843,22 +1837,83 @@ pub fn run_lox_function(
     Ok(LiteralValue::Nil)
 }
 
-pub fn find_method(name: &str, class: LiteralValue) -> Option<LoxFunctionImpl> {
-    if let LoxClass {
-        name: _,
-        methods,
-        superclass,
-    } = class
-    {
-        if let Some(fun) = methods.get(name) {
-            return Some(fun.clone());
+fn list_index(index: &LiteralValue, len: usize) -> Result<usize, String> {
+    match index {
+        Number(x) if x.fract() == 0.0 && *x >= 0.0 && (*x as usize) < len => Ok(*x as usize),
+        Number(x) if x.fract() == 0.0 => Err(format!(
+            "Index {} out of range for length {}",
+            x, len
+        )),
+        other => Err(format!(
+            "Index must be a whole number, got {}",
+            other.to_string()
+        )),
+    }
+}
+
+// Assigns the values of a tuple to the variables of `(a, (b, c)) = ...`
+fn assign_tuple(
+    targets: &[Expr],
+    value: &LiteralValue,
+    environment: &Environment,
+) -> Result<(), String> {
+    let values = match value {
+        Tuple(values) if values.len() == targets.len() => values,
+        other => {
+            return Err(format!(
+                "Cannot assign {} to a tuple of {} targets",
+                other.to_string(),
+                targets.len()
+            ))
         }
-        if let Some(superclass) = superclass {
-            return find_method(name, *superclass.clone());
+    };
+
+    for (target, value) in targets.iter().zip(values.iter()) {
+        match target {
+            Expr::Variable { id, name } => environment.assign(&name.lexeme, value.clone(), *id)?,
+            Expr::Tuple { id: _, elements } => assign_tuple(elements, value, environment)?,
+            _ => panic!("Invalid tuple assignment target"),
         }
-        None
+    }
+    Ok(())
+}
+
+fn operator_method(operator: TokenType) -> Option<&'static str> {
+    match operator {
+        TokenType::Plus => Some("__add"),
+        TokenType::Minus => Some("__sub"),
+        TokenType::Star => Some("__mul"),
+        TokenType::Slash => Some("__div"),
+        TokenType::EqualEqual | TokenType::BangEqual => Some("__eq"),
+        TokenType::Less => Some("__lt"),
+        TokenType::LessEqual => Some("__le"),
+        TokenType::Greater => Some("__gt"),
+        TokenType::GreaterEqual => Some("__ge"),
+        _ => None,
+    }
+}
+
+// Calls a special method like `__add` or `__str` if the value is an instance whose
+// class (or a superclass) defines it
+pub fn call_special(
+    value: &LiteralValue,
+    name: &str,
+    arguments: Vec<LiteralValue>,
+) -> Option<Result<LiteralValue, String>> {
+    let method = bind_method(value, name)?;
+    Some(run_lox_function(method, arguments, vec![]))
+}
+
+// Looks up a method on the class of an instance and binds 'this' to the instance
+pub fn bind_method(instance: &LiteralValue, name: &str) -> Option<LoxFunctionImpl> {
+    if let LoxInstance { class, .. } = instance {
+        let mut method = class.find_method(name)?;
+        let new_env = method.parent_env.enclose();
+        new_env.define("this".to_string(), instance.clone());
+        method.parent_env = new_env;
+        Some(method)
     } else {
-        panic!("Cannot find method on non-class");
+        None
     }
 }
 
@@ -875,6 +1930,7 @@ mod tests {
             lexeme: "-".to_string(),
             literal: None,
             line_number: 0,
+            column: 0,
         };
         let onetwothree = Literal {
             id: 0,
@@ -892,6 +1948,7 @@ mod tests {
             lexeme: "*".to_string(),
             literal: None,
             line_number: 0,
+            column: 0,
         };
         let ast = Binary {
             id: 3,
@@ -916,6 +1973,7 @@ mod tests {
             lexeme: "-".to_string(),
             literal: None,
             line_number: 0,
+            column: 0,
         };
         let onetwothree = Literal {
             id: 0,
@@ -933,6 +1991,7 @@ mod tests {
             lexeme: "*".to_string(),
             literal: None,
             line_number: 0,
+            column: 0,
         };
         let expr = Binary {
             id: 3,
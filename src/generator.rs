@@ -0,0 +1,443 @@
+use crate::environment::Environment;
+use crate::expr::{bind_method, run_lox_function, Expr, LiteralValue, LoxFunctionImpl};
+use crate::interpreter::Interpreter;
+use crate::sandbox::Runtime;
+use crate::scanner::Token;
+use crate::stmt::Stmt;
+use std::cell::{Cell, RefCell};
+use std::rc::Rc;
+
+// True if the statements yield, which makes the function they are the body of a
+// generator. Yields in nested functions belong to those functions.
+pub fn contains_yield(stmts: &[Box<Stmt>]) -> bool {
+    stmts.iter().any(|stmt| stmt_yields(stmt))
+}
+
+fn stmt_yields(stmt: &Stmt) -> bool {
+    match stmt {
+        Stmt::Yield { .. } => true,
+        Stmt::Block { statements } => contains_yield(statements),
+        Stmt::IfStmt {
+            predicate: _,
+            then,
+            els,
+        } => stmt_yields(then) || els.as_ref().is_some_and(|els| stmt_yields(els)),
+        Stmt::WhileStmt { condition: _, body } => stmt_yields(body),
+        Stmt::ForIn {
+            variable: _,
+            iterable: _,
+            body,
+        } => stmt_yields(body),
+        _ => false,
+    }
+}
+
+// The values a for-in loop runs over, taken one at a time
+pub enum Iteration {
+    Range {
+        next: f64,
+        end: f64,
+        inclusive: bool,
+    },
+    // A snapshot, so the loop body can modify the list
+    Values(std::vec::IntoIter<LiteralValue>),
+    // The next() method of an instance, called until it returns nil
+    Iterator(LoxFunctionImpl),
+    Generator(Rc<GeneratorImpl>),
+}
+
+impl Iteration {
+    pub fn new(iterable: &LiteralValue) -> Result<Self, String> {
+        match iterable {
+            LiteralValue::Range {
+                start,
+                end,
+                inclusive,
+            } => Ok(Iteration::Range {
+                next: *start,
+                end: *end,
+                inclusive: *inclusive,
+            }),
+            LiteralValue::List(items) => Ok(Iteration::Values(items.borrow().clone().into_iter())),
+            LiteralValue::Tuple(items) => Ok(Iteration::Values(Vec::clone(items).into_iter())),
+            LiteralValue::StringValue(s) => {
+                let chars: Vec<LiteralValue> = s
+                    .chars()
+                    .map(|c| LiteralValue::StringValue(c.to_string()))
+                    .collect();
+                Ok(Iteration::Values(chars.into_iter()))
+            }
+            LiteralValue::LoxInstance { .. } => {
+                // Iterator protocol: iter() returns an iterator (or the instance
+                // itself is one), next() is called until it returns nil
+                let iterator = match bind_method(iterable, "iter") {
+                    Some(iter) => run_lox_function(iter, vec![], vec![])?,
+                    None => iterable.clone(),
+                };
+                if let LiteralValue::Generator(generator) = iterator {
+                    return Ok(Iteration::Generator(generator));
+                }
+                match bind_method(&iterator, "next") {
+                    Some(next) => Ok(Iteration::Iterator(next)),
+                    None => Err(format!(
+                        "Cannot iterate over {}, it has no next() method",
+                        iterator.to_type()
+                    )),
+                }
+            }
+            LiteralValue::Generator(generator) => Ok(Iteration::Generator(generator.clone())),
+            other => Err(format!("Cannot iterate over {}", other.to_type())),
+        }
+    }
+
+    // Each value taken is a step, so draining a long range or iterator counts
+    // against the step budget like a loop does
+    pub fn next_item(&mut self, runtime: &Runtime) -> Result<Option<LiteralValue>, String> {
+        runtime.step()?;
+        match self {
+            Iteration::Range {
+                next,
+                end,
+                inclusive,
+            } => {
+                if *next < *end || (*inclusive && *next == *end) {
+                    let value = LiteralValue::Number(*next);
+                    *next += 1.0;
+                    Ok(Some(value))
+                } else {
+                    Ok(None)
+                }
+            }
+            Iteration::Values(values) => Ok(values.next()),
+            Iteration::Iterator(next) => match run_lox_function(next.clone(), vec![], vec![])? {
+                LiteralValue::Nil => Ok(None),
+                value => Ok(Some(value)),
+            },
+            Iteration::Generator(generator) => generator.resume(),
+        }
+    }
+}
+
+// Where a suspended coroutine continues. Statements that can contain `yield` or
+// `await` get a frame, all others run to completion in an Interpreter.
+enum Frame {
+    Block {
+        stmts: Rc<Vec<Box<Stmt>>>,
+        next: usize,
+        environment: Environment,
+    },
+    While {
+        condition: Expr,
+        body: Box<Stmt>,
+        environment: Environment,
+    },
+    ForIn {
+        variable: Token,
+        items: Iteration,
+        body: Box<Stmt>,
+        environment: Environment,
+    },
+    // A statement suspended at its `await`, finished with the awaited value
+    Resume {
+        stmt: Box<Stmt>,
+        environment: Environment,
+    },
+}
+
+// Why a coroutine stopped running
+pub enum Suspend {
+    Yield(LiteralValue),
+    // Waiting for a task, or for any other value, which is ready right away
+    Await(LiteralValue),
+    Return(LiteralValue),
+}
+
+// A function body that runs a bit at a time, keeping its place in a stack of
+// frames in between. Generators and tasks are built on it.
+pub struct Coroutine {
+    name: String,
+    runtime: Rc<Runtime>,
+    frames: RefCell<Vec<Frame>>,
+}
+
+impl Coroutine {
+    pub fn new(name: &str, body: Rc<Vec<Box<Stmt>>>, environment: Environment) -> Self {
+        Self {
+            name: name.to_string(),
+            runtime: environment.runtime.clone(),
+            frames: RefCell::new(vec![Frame::Block {
+                stmts: body,
+                next: 0,
+                environment,
+            }]),
+        }
+    }
+
+    // Runs the body until it suspends. `sent` is the result of the await it was
+    // suspended at, if any. Every resume counts as a call, so that generators and
+    // tasks that recurse hit the call depth limit.
+    pub fn resume(&self, sent: Result<LiteralValue, String>) -> Result<Suspend, String> {
+        let result = self.runtime.enter_call(&self.name).and_then(|_| {
+            let result = self.run(sent);
+            self.runtime.exit_call();
+            result
+        });
+
+        // A coroutine that failed is finished
+        if result.is_err() {
+            self.frames.borrow_mut().clear();
+        }
+        result
+    }
+
+    fn run(&self, sent: Result<LiteralValue, String>) -> Result<Suspend, String> {
+        let mut sent = Some(sent);
+        loop {
+            let frame = self.frames.borrow_mut().pop();
+            let suspend = match frame {
+                None => return Ok(Suspend::Return(LiteralValue::Nil)),
+                Some(Frame::Block {
+                    stmts,
+                    next,
+                    environment,
+                }) => {
+                    if next >= stmts.len() {
+                        continue;
+                    }
+                    self.push(Frame::Block {
+                        stmts: stmts.clone(),
+                        next: next + 1,
+                        environment: environment.clone(),
+                    });
+                    self.step(&stmts[next], &environment)?
+                }
+                Some(Frame::While {
+                    condition,
+                    body,
+                    environment,
+                }) => {
+                    let flag = condition.evaluate(environment.clone())?;
+                    if flag.is_truthy() == LiteralValue::True {
+                        let body_frame = single(&body, environment.clone());
+                        self.push(Frame::While {
+                            condition,
+                            body,
+                            environment,
+                        });
+                        self.push(body_frame);
+                    }
+                    None
+                }
+                Some(Frame::ForIn {
+                    variable,
+                    mut items,
+                    body,
+                    environment,
+                }) => {
+                    if let Some(item) = items.next_item(&environment.runtime)? {
+                        let loop_environment = environment.enclose();
+                        loop_environment.define(variable.lexeme.clone(), item);
+                        let body_frame = single(&body, loop_environment);
+                        self.push(Frame::ForIn {
+                            variable,
+                            items,
+                            body,
+                            environment,
+                        });
+                        self.push(body_frame);
+                    }
+                    None
+                }
+                Some(Frame::Resume { stmt, environment }) => {
+                    let value = sent.take().unwrap_or(Ok(LiteralValue::Nil))?;
+                    finish_await(&stmt, value, &environment)?
+                }
+            };
+
+            if let Some(suspend) = suspend {
+                if let Suspend::Return(_) = suspend {
+                    self.frames.borrow_mut().clear();
+                }
+                return Ok(suspend);
+            }
+        }
+    }
+
+    // Runs one statement, or pushes the frames for it. Some(..) stops the coroutine.
+    fn step(&self, stmt: &Stmt, environment: &Environment) -> Result<Option<Suspend>, String> {
+        if let Some(awaited) = awaited(stmt) {
+            environment.runtime.step()?;
+            let value = awaited.evaluate(environment.clone())?;
+            self.push(Frame::Resume {
+                stmt: Box::new(stmt.clone()),
+                environment: environment.clone(),
+            });
+            return Ok(Some(Suspend::Await(value)));
+        }
+
+        match stmt {
+            Stmt::Yield { keyword: _, value } => {
+                environment.runtime.step()?;
+                let value = match value {
+                    Some(value) => value.evaluate(environment.clone())?,
+                    None => LiteralValue::Nil,
+                };
+                Ok(Some(Suspend::Yield(value)))
+            }
+            Stmt::ReturnStmt { keyword: _, value } => {
+                environment.runtime.step()?;
+                let value = match value {
+                    Some(value) => value.evaluate(environment.clone())?,
+                    None => LiteralValue::Nil,
+                };
+                Ok(Some(Suspend::Return(value)))
+            }
+            Stmt::Block { statements } => {
+                environment.runtime.step()?;
+                self.push(Frame::Block {
+                    stmts: Rc::new(statements.clone()),
+                    next: 0,
+                    environment: environment.enclose(),
+                });
+                Ok(None)
+            }
+            Stmt::IfStmt {
+                predicate,
+                then,
+                els,
+            } => {
+                environment.runtime.step()?;
+                let truth_value = predicate.evaluate(environment.clone())?;
+                if truth_value.is_truthy() == LiteralValue::True {
+                    self.push(single(then, environment.clone()));
+                } else if let Some(els) = els {
+                    self.push(single(els, environment.clone()));
+                }
+                Ok(None)
+            }
+            Stmt::WhileStmt { condition, body } => {
+                environment.runtime.step()?;
+                self.push(Frame::While {
+                    condition: condition.clone(),
+                    body: body.clone(),
+                    environment: environment.clone(),
+                });
+                Ok(None)
+            }
+            Stmt::ForIn {
+                variable,
+                iterable,
+                body,
+            } => {
+                environment.runtime.step()?;
+                let iterable = iterable.evaluate(environment.clone())?;
+                self.push(Frame::ForIn {
+                    variable: variable.clone(),
+                    items: Iteration::new(&iterable)?,
+                    body: body.clone(),
+                    environment: environment.clone(),
+                });
+                Ok(None)
+            }
+            other => {
+                let mut interpreter = Interpreter::with_env(environment.clone());
+                interpreter.interpret(vec![other])?;
+                Ok(None)
+            }
+        }
+    }
+
+    fn push(&self, frame: Frame) {
+        self.frames.borrow_mut().push(frame);
+    }
+}
+
+// The activation of a function that yields. Its body runs a bit further on every
+// call to next().
+pub struct GeneratorImpl {
+    pub name: String,
+    coroutine: Coroutine,
+    // A generator can not resume itself from inside its body
+    running: Cell<bool>,
+}
+
+impl GeneratorImpl {
+    pub fn new(name: String, body: Rc<Vec<Box<Stmt>>>, environment: Environment) -> Self {
+        Self {
+            coroutine: Coroutine::new(&name, body, environment),
+            name,
+            running: Cell::new(false),
+        }
+    }
+
+    // Runs the body up to the next `yield`, None once it has finished
+    pub fn resume(&self) -> Result<Option<LiteralValue>, String> {
+        if self.running.replace(true) {
+            return Err(format!("Generator {} is already running", self.name));
+        }
+        let result = self.coroutine.resume(Ok(LiteralValue::Nil));
+        self.running.set(false);
+
+        match result? {
+            Suspend::Yield(value) => Ok(Some(value)),
+            Suspend::Return(_) => Ok(None),
+            Suspend::Await(_) => Err(format!("Generator {} cannot await", self.name)),
+        }
+    }
+}
+
+// A frame that runs one statement in the given environment
+fn single(stmt: &Stmt, environment: Environment) -> Frame {
+    Frame::Block {
+        stmts: Rc::new(vec![Box::new(stmt.clone())]),
+        next: 0,
+        environment,
+    }
+}
+
+// The `await` a statement starts with. These are the only places where a task can
+// suspend, so the resolver refuses an await anywhere else in an async function.
+pub fn leading_await(stmt: &Stmt) -> Option<&Expr> {
+    let expression = match stmt {
+        Stmt::Expression {
+            expression: Expr::Assign { value, .. },
+        } => value.as_ref(),
+        Stmt::Expression { expression } | Stmt::Print { expression } => expression,
+        Stmt::Var { initializer, .. } => initializer,
+        Stmt::ReturnStmt {
+            keyword: _,
+            value: Some(value),
+        } => value,
+        _ => return None,
+    };
+    match expression {
+        Expr::Await { .. } => Some(expression),
+        _ => None,
+    }
+}
+
+// What a statement starting with `await` waits for
+fn awaited(stmt: &Stmt) -> Option<&Expr> {
+    match leading_await(stmt)? {
+        Expr::Await { value, .. } => Some(value),
+        _ => None,
+    }
+}
+
+// Does the rest of a statement suspended by awaited(), with the awaited value
+fn finish_await(
+    stmt: &Stmt,
+    value: LiteralValue,
+    environment: &Environment,
+) -> Result<Option<Suspend>, String> {
+    match stmt {
+        Stmt::Expression {
+            expression: Expr::Assign { id, name, .. },
+        } => environment.assign(&name.lexeme, value, *id)?,
+        Stmt::Print { .. } => environment.runtime.print(&value.to_display_string()?),
+        Stmt::Var { name, .. } => environment.declare(name.lexeme.clone(), value)?,
+        Stmt::ReturnStmt { .. } => return Ok(Some(Suspend::Return(value))),
+        _ => (),
+    }
+    Ok(None)
+}
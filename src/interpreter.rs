@@ -1,5 +1,10 @@
 use crate::environment::Environment;
-use crate::expr::{CallableImpl, LiteralValue, LoxFunctionImpl, NativeFunctionImpl};
+use crate::expr::{
+    CallableImpl, LiteralValue, LoxClassImpl, LoxEnumImpl, LoxFunctionImpl, LoxTraitImpl,
+    LoxVariantImpl, NativeClosure, NativeFunctionImpl, Parameter,
+};
+use crate::generator::{contains_yield, Iteration};
+use crate::sandbox::{Capability, Config};
 use crate::scanner::Token;
 use crate::stmt::Stmt;
 use std::collections::HashMap;
@@ -11,6 +16,12 @@ pub struct Interpreter {
     pub environment: Environment,
 }
 
+impl Default for Interpreter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Interpreter {
     pub fn new() -> Self {
         Self {
@@ -19,10 +30,31 @@ impl Interpreter {
         }
     }
 
+    pub fn with_config(config: Config) -> Self {
+        Self {
+            specials: HashMap::new(),
+            environment: Environment::with_config(HashMap::new(), config),
+        }
+    }
+
+    // Makes `print` collect its output for take_output instead of writing to stdout
+    pub fn capture_output(&self) {
+        self.environment.runtime.capture_output();
+    }
+
+    pub fn take_output(&self) -> String {
+        self.environment.runtime.take_output()
+    }
+
     pub fn resolve(&mut self, locals: HashMap<usize, usize>) {
         self.environment.resolve(locals);
     }
 
+    // Runs the event loop until every task has finished or is stuck waiting
+    pub fn run_tasks(&self) -> Result<(), String> {
+        self.environment.runtime.event_loop.run()
+    }
+
     pub fn with_env(env: Environment) -> Self {
         Self {
             specials: HashMap::new(),
@@ -41,17 +73,48 @@ impl Interpreter {
 
     pub fn interpret(&mut self, stmts: Vec<&Stmt>) -> Result<(), String> {
         for stmt in stmts {
+            if self.is_returning() {
+                break;
+            }
+            self.environment.runtime.step()?;
+
             match stmt {
                 Stmt::Expression { expression } => {
                     expression.evaluate(self.environment.clone())?;
                 }
                 Stmt::Print { expression } => {
                     let value = expression.evaluate(self.environment.clone())?;
-                    println!("{}", value.to_string());
+                    self.environment.runtime.print(&value.to_display_string()?);
+                }
+                Stmt::Var {
+                    name,
+                    annotation: _,
+                    initializer,
+                } => {
+                    let value = initializer.evaluate(self.environment.clone())?;
+                    self.environment.declare(name.lexeme.clone(), value)?;
                 }
-                Stmt::Var { name, initializer } => {
+                Stmt::Const { name, initializer } => {
                     let value = initializer.evaluate(self.environment.clone())?;
-                    self.environment.define(name.lexeme.clone(), value);
+                    self.environment.define_const(name.lexeme.clone(), value)?;
+                }
+                Stmt::Destructure {
+                    keyword,
+                    pattern,
+                    initializer,
+                } => {
+                    let value = initializer.evaluate(self.environment.clone())?;
+                    let bindings = self.environment.enclose();
+                    if !pattern.matches(&value, &bindings) {
+                        return Err(format!(
+                            "Line {}: Cannot destructure {}",
+                            keyword.line_number,
+                            value.to_string()
+                        ));
+                    }
+                    for (name, value) in bindings.values.borrow().iter() {
+                        self.environment.declare(name.clone(), value.clone())?;
+                    }
                 }
                 Stmt::Block { statements } => {
                     let new_environment = self.environment.enclose();
@@ -68,17 +131,16 @@ impl Interpreter {
                 }
                 Stmt::Class {
                     name,
+                    fields: _,
                     methods,
                     superclass,
+                    traits,
                 } => {
-                    let mut methods_map = HashMap::new();
-
-                    // Insert the methods of the superclass into the methods of this class
                     let superclass_value;
                     if let Some(superclass) = superclass {
                         let superclass = superclass.evaluate(self.environment.clone())?;
-                        if let LiteralValue::LoxClass { .. } = superclass {
-                            superclass_value = Some(Box::new(superclass));
+                        if let LiteralValue::LoxClass(superclass) = superclass {
+                            superclass_value = Some(superclass);
                         } else {
                             return Err(format!(
                                 "Superclass must be a class, not {}",
@@ -89,41 +151,122 @@ impl Interpreter {
                         superclass_value = None;
                     }
 
+                    let mut trait_values = vec![];
+                    for trait_ in traits {
+                        match trait_.evaluate(self.environment.clone())? {
+                            LiteralValue::LoxTrait(trait_) => trait_values.push(trait_),
+                            other => {
+                                return Err(format!(
+                                    "Can only mix in traits, not {}",
+                                    other.to_type()
+                                ))
+                            }
+                        }
+                    }
+
                     self.environment
-                        .define(name.lexeme.clone(), LiteralValue::Nil);
+                        .declare(name.lexeme.clone(), LiteralValue::Nil)?;
 
-                    self.environment = self.environment.enclose();
-                    if let Some(sc) = superclass_value.clone() {
-                        self.environment.define("super".to_string(), *sc);
-                    }
+                    // Like the resolver, only classes with a superclass get a scope for 'super'
+                    let own_methods = match &superclass_value {
+                        Some(sc) => self.make_methods(methods, LiteralValue::LoxClass(sc.clone())),
+                        None => methods.iter().map(|m| self.make_method(m)).collect(),
+                    };
+                    // A class that declares abstract methods itself is abstract and may
+                    // leave methods unimplemented
+                    let is_abstract = own_methods.values().any(|m| m.is_abstract);
 
-                    for method in methods {
-                        if let Stmt::Function {
-                            name,
-                            params: _,
-                            body: _,
-                        } = method.as_ref()
-                        {
-                            let function = self.make_function(method);
-                            methods_map.insert(name.lexeme.clone(), function);
-                        } else {
-                            panic!(
-                                "Something that was not a function was in the methods of a class"
-                            );
+                    // Trait methods are copied in, the class's own methods win. Required
+                    // trait methods do not hide ones inherited from the superclass.
+                    let mut methods_map = HashMap::new();
+                    for trait_ in trait_values.iter() {
+                        for (name, method) in trait_.methods.iter() {
+                            let inherited = superclass_value
+                                .as_ref()
+                                .and_then(|sc| sc.find_method(name))
+                                .is_some_and(|m| !m.is_abstract);
+                            if !(method.is_abstract && inherited) {
+                                methods_map.insert(name.clone(), method.clone());
+                            }
                         }
                     }
+                    methods_map.extend(own_methods);
+
+                    let class = LoxClassImpl::new(
+                        name.lexeme.clone(),
+                        methods_map,
+                        superclass_value,
+                        trait_values,
+                    );
+                    if !is_abstract {
+                        class.check_implemented()?;
+                    }
+                    let klass = LiteralValue::LoxClass(Rc::new(class));
+
+                    // Class names can not be reassigned once defined
+                    self.environment.define_const(name.lexeme.clone(), klass)?;
+                }
+                Stmt::Enum { name, variants } => {
+                    let mut members = HashMap::new();
+                    for variant in variants {
+                        let variant_impl = Rc::new(LoxVariantImpl {
+                            enum_name: name.lexeme.clone(),
+                            name: variant.name.lexeme.clone(),
+                            fields: variant
+                                .fields
+                                .iter()
+                                .flatten()
+                                .map(|field| field.lexeme.clone())
+                                .collect(),
+                        });
+                        let member = match variant.fields {
+                            Some(_) => LiteralValue::EnumVariant(variant_impl),
+                            None => LiteralValue::EnumValue {
+                                variant: variant_impl,
+                                values: Rc::new(vec![]),
+                            },
+                        };
+                        members.insert(variant.name.lexeme.clone(), member);
+                    }
 
-                    let klass = LiteralValue::LoxClass {
+                    let enum_ = LoxEnumImpl {
                         name: name.lexeme.clone(),
-                        methods: methods_map,
-                        superclass: superclass_value,
+                        members,
+                    };
+                    self.environment
+                        .define_const(name.lexeme.clone(), LiteralValue::LoxEnum(Rc::new(enum_)))?;
+                }
+                Stmt::Trait { name, methods } => {
+                    let methods = methods.iter().map(|m| self.make_method(m)).collect();
+                    let trait_ = LoxTraitImpl {
+                        name: name.lexeme.clone(),
+                        methods,
+                    };
+                    self.environment.define_const(
+                        name.lexeme.clone(),
+                        LiteralValue::LoxTrait(Rc::new(trait_)),
+                    )?;
+                }
+                Stmt::AbstractMethod { .. } => {
+                    panic!("Abstract method outside of a class body")
+                }
+                Stmt::Extend { class, methods } => {
+                    let class = match class.evaluate(self.environment.clone())? {
+                        LiteralValue::LoxClass(class) => class,
+                        other => {
+                            return Err(format!("Can only extend a class, not {}", other.to_type()))
+                        }
                     };
 
-                    if !self.environment.assign_global(&name.lexeme, klass) {
-                        return Err(format!("Class definition failed for {}", name.lexeme));
+                    // The resolver can not know if the class has a superclass, so extensions
+                    // always get a scope for 'super'
+                    let superclass = match &class.superclass {
+                        Some(superclass) => LiteralValue::LoxClass(superclass.clone()),
+                        None => LiteralValue::Nil,
+                    };
+                    for (name, method) in self.make_methods(methods, superclass) {
+                        class.define_method(name, method);
                     }
-
-                    self.environment = *self.environment.enclosing.clone().unwrap();
                 }
                 Stmt::IfStmt {
                     predicate,
@@ -144,47 +287,88 @@ impl Interpreter {
                     while flag.is_truthy() == LiteralValue::True {
                         let statements = vec![body.as_ref()];
                         self.interpret(statements)?;
+                        if self.is_returning() {
+                            break;
+                        }
                         flag = condition.evaluate(self.environment.clone())?;
                     }
                 }
+                Stmt::ForIn {
+                    variable,
+                    iterable,
+                    body,
+                } => {
+                    let iterable = iterable.evaluate(self.environment.clone())?;
+                    let mut items = Iteration::new(&iterable)?;
+                    while let Some(item) = items.next_item(&self.environment.runtime)? {
+                        if !self.run_loop_body(variable, item, body)? {
+                            break;
+                        }
+                    }
+                }
                 Stmt::Function {
                     name,
                     params: _,
+                    return_type: _,
                     body: _,
+                    is_async: _,
                 } => {
                     let callable = self.make_function(stmt);
                     let fun = LiteralValue::Callable(CallableImpl::LoxFunction(callable));
-                    self.environment.define(name.lexeme.clone(), fun);
+                    self.environment.declare(name.lexeme.clone(), fun)?;
                 }
-                Stmt::CmdFunction { name, cmd } => {
+                Stmt::CmdFunction {
+                    name,
+                    cmd,
+                    is_async,
+                } => {
+                    self.environment.runtime.require(
+                        Capability::Commands,
+                        &format!("Command function '{}'", name.lexeme),
+                    )?;
+
                     // Return a callable that runs a shell command, captures the stdout and returns
                     // it in a String
 
                     let cmd = cmd.clone();
-                    let local_fn = move |_args: &Vec<LiteralValue>| {
-                        let cmd = cmd.clone();
-                        let parts = cmd.split(" ").collect::<Vec<&str>>();
-                        let mut command = Command::new(parts[0].replace("\"", ""));
-                        for part in parts[1..].iter() {
-                            command.arg(part.replace("\"", ""));
-                        }
-                        let output = command.output().expect("Failed to run command");
-
+                    let fun: Rc<NativeClosure> = if *is_async {
+                        // Returns a task right away, the command runs while other tasks do
+                        let event_loop = self.environment.runtime.event_loop.clone();
+                        let name = name.lexeme.clone();
+                        Rc::new(move |_args: &[LiteralValue]| {
+                            let task = event_loop.run_command(&name, &cmd, shell_command(&cmd));
+                            Ok(LiteralValue::Task(task))
+                        })
+                    } else {
+                        Rc::new(move |_args: &[LiteralValue]| {
+                            let output = match shell_command(&cmd).output() {
+                                Ok(output) => output,
+                                Err(e) => {
+                                    return Err(format!("Failed to run command '{}': {}", cmd, e))
+                                }
+                            };
 
-                        return LiteralValue::StringValue(
-                            std::str::from_utf8(output.stdout.as_slice())
-                                .unwrap()
-                                .to_string(),
-                        );
+                            Ok(LiteralValue::StringValue(
+                                String::from_utf8_lossy(output.stdout.as_slice()).to_string(),
+                            ))
+                        })
                     };
 
                     let fun_val =
                         LiteralValue::Callable(CallableImpl::NativeFunction(NativeFunctionImpl {
                             name: name.lexeme.clone(),
                             arity: 0,
-                            fun: Rc::new(local_fn),
+                            variadic: false,
+                            fun,
                         }));
-                    self.environment.define(name.lexeme.clone(), fun_val);
+                    self.environment.declare(name.lexeme.clone(), fun_val)?;
+                }
+                Stmt::Yield { keyword, value: _ } => {
+                    // Generator bodies are run by GeneratorImpl, which handles yields
+                    return Err(format!(
+                        "Line {}: Cannot yield outside of a generator",
+                        keyword.line_number
+                    ));
                 }
                 Stmt::ReturnStmt { keyword: _, value } => {
                     let eval_val;
@@ -201,10 +385,81 @@ impl Interpreter {
         Ok(())
     }
 
+    fn is_returning(&self) -> bool {
+        self.specials.contains_key("return")
+    }
+
+    // Runs one iteration of a for-in loop, returns false if the loop should stop
+    fn run_loop_body(
+        &mut self,
+        variable: &Token,
+        value: LiteralValue,
+        body: &Stmt,
+    ) -> Result<bool, String> {
+        let loop_environment = self.environment.enclose();
+        loop_environment.define(variable.lexeme.clone(), value);
+
+        let old_environment = self.environment.clone();
+        self.environment = loop_environment;
+        let body_result = self.interpret(vec![body]);
+        self.environment = old_environment;
+        body_result?;
+
+        Ok(!self.is_returning())
+    }
+
+    // Methods of a class body, closing over a scope that holds 'super'
+    fn make_methods(
+        &mut self,
+        methods: &[Box<Stmt>],
+        superclass: LiteralValue,
+    ) -> HashMap<String, LoxFunctionImpl> {
+        let old_environment = self.environment.clone();
+        self.environment = self.environment.enclose();
+        self.environment.define("super".to_string(), superclass);
+
+        let methods = methods.iter().map(|m| self.make_method(m)).collect();
+
+        self.environment = old_environment;
+        methods
+    }
+
+    fn make_method(&self, method: &Stmt) -> (String, LoxFunctionImpl) {
+        match method {
+            Stmt::Function { name, .. } => {
+                let mut function = self.make_function(method);
+                function.is_initializer = name.lexeme == "init";
+                (name.lexeme.clone(), function)
+            }
+            Stmt::AbstractMethod { name, params } => {
+                let function = LoxFunctionImpl {
+                    name: name.lexeme.clone(),
+                    arity: params.iter().filter(|p| !p.is_rest).count(),
+                    parent_env: self.environment.clone(),
+                    params: params.clone(),
+                    body: Rc::new(vec![]),
+                    is_initializer: false,
+                    is_abstract: true,
+                    is_generator: false,
+                    is_async: false,
+                };
+                (name.lexeme.clone(), function)
+            }
+            _ => panic!("Something that was not a function was in the methods of a class"),
+        }
+    }
+
     fn make_function(&self, fn_stmt: &Stmt) -> LoxFunctionImpl {
-        if let Stmt::Function { name, params, body } = fn_stmt {
-            let arity = params.len();
-            let params: Vec<Token> = params.iter().map(|t| (*t).clone()).collect();
+        if let Stmt::Function {
+            name,
+            params,
+            return_type: _,
+            body,
+            is_async,
+        } = fn_stmt
+        {
+            let arity = params.iter().filter(|p| !p.is_rest).count();
+            let params: Vec<Parameter> = params.iter().map(|t| (*t).clone()).collect();
             let body: Vec<Box<Stmt>> = body.iter().map(|b| (*b).clone()).collect();
             let name_clone = name.lexeme.clone();
 
@@ -216,7 +471,11 @@ impl Interpreter {
                 arity,
                 parent_env,
                 params,
-                body,
+                is_generator: contains_yield(&body),
+                body: Rc::new(body),
+                is_initializer: false,
+                is_abstract: false,
+                is_async: *is_async,
             };
 
             callable_impl
@@ -225,3 +484,13 @@ impl Interpreter {
         }
     }
 }
+
+// The command of `fun name <- "cmd";`, its words split on spaces
+fn shell_command(cmd: &str) -> Command {
+    let parts = cmd.split(" ").collect::<Vec<&str>>();
+    let mut command = Command::new(parts[0].replace("\"", ""));
+    for part in parts[1..].iter() {
+        command.arg(part.replace("\"", ""));
+    }
+    command
+}
@@ -0,0 +1,379 @@
+use crate::compile;
+use crate::expr::{call_value, LiteralValue};
+use crate::interpreter::Interpreter;
+use crate::natives::record;
+use crate::sandbox::Config;
+use crate::stmt::Stmt;
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::rc::Rc;
+
+// A value copied out of one interpreter to be rebuilt in another, which may run on
+// a different thread. Functions, tasks and other values that belong to an
+// interpreter can not be copied.
+#[derive(Debug, Clone, PartialEq)]
+pub enum SharedValue {
+    Nil,
+    Boolean(bool),
+    Number(f64),
+    String(String),
+    Range {
+        start: f64,
+        end: f64,
+        inclusive: bool,
+    },
+    List(Vec<SharedValue>),
+    Tuple(Vec<SharedValue>),
+    // Rebuilt as an instance of the class with the same name
+    Instance {
+        class: String,
+        fields: Vec<(String, SharedValue)>,
+        frozen: bool,
+    },
+    EnumValue {
+        enum_name: String,
+        variant: String,
+        values: Vec<SharedValue>,
+    },
+}
+
+impl SharedValue {
+    pub fn copy(value: &LiteralValue) -> Result<Self, String> {
+        copy_value(value, &mut vec![])
+    }
+
+    // Looks up classes and enums by name in the globals of the interpreter the value
+    // is rebuilt in. Instances of classes it does not know become plain records.
+    pub fn rebuild(&self, globals: &HashMap<String, LiteralValue>) -> Result<LiteralValue, String> {
+        let rebuild_all = |values: &[SharedValue]| {
+            values
+                .iter()
+                .map(|value| value.rebuild(globals))
+                .collect::<Result<Vec<_>, String>>()
+        };
+
+        match self {
+            SharedValue::Nil => Ok(LiteralValue::Nil),
+            SharedValue::Boolean(b) => Ok(LiteralValue::from_bool(*b)),
+            SharedValue::Number(x) => Ok(LiteralValue::Number(*x)),
+            SharedValue::String(s) => Ok(LiteralValue::StringValue(s.clone())),
+            SharedValue::Range {
+                start,
+                end,
+                inclusive,
+            } => Ok(LiteralValue::Range {
+                start: *start,
+                end: *end,
+                inclusive: *inclusive,
+            }),
+            SharedValue::List(items) => Ok(LiteralValue::List(Rc::new(RefCell::new(rebuild_all(
+                items,
+            )?)))),
+            SharedValue::Tuple(items) => Ok(LiteralValue::Tuple(Rc::new(rebuild_all(items)?))),
+            SharedValue::Instance {
+                class,
+                fields,
+                frozen,
+            } => {
+                let mut rebuilt = vec![];
+                for (name, value) in fields {
+                    rebuilt.push((name.clone(), value.rebuild(globals)?));
+                }
+                let instance = match globals.get(class) {
+                    Some(LiteralValue::LoxClass(class)) => LiteralValue::LoxInstance {
+                        class: class.clone(),
+                        fields: Rc::new(RefCell::new(rebuilt.into_iter().collect())),
+                        frozen: Rc::new(RefCell::new(false)),
+                    },
+                    _ => record(class, rebuilt),
+                };
+                if let LiteralValue::LoxInstance { frozen: flag, .. } = &instance {
+                    *flag.borrow_mut() = *frozen;
+                }
+                Ok(instance)
+            }
+            SharedValue::EnumValue {
+                enum_name,
+                variant,
+                values,
+            } => {
+                let member = match globals.get(enum_name) {
+                    Some(LiteralValue::LoxEnum(enum_)) => enum_.members.get(variant).cloned(),
+                    _ => None,
+                };
+                match member {
+                    Some(LiteralValue::EnumVariant(variant)) => Ok(LiteralValue::EnumValue {
+                        variant,
+                        values: Rc::new(rebuild_all(values)?),
+                    }),
+                    Some(value) => Ok(value),
+                    None => Err(format!(
+                        "Enum {} has no variant named {}",
+                        enum_name, variant
+                    )),
+                }
+            }
+        }
+    }
+}
+
+// `containers` holds the lists and instances being copied, to refuse cycles
+fn copy_value(
+    value: &LiteralValue,
+    containers: &mut Vec<*const ()>,
+) -> Result<SharedValue, String> {
+    let copy_all = |values: &[LiteralValue], containers: &mut Vec<*const ()>| {
+        values
+            .iter()
+            .map(|value| copy_value(value, containers))
+            .collect::<Result<Vec<_>, String>>()
+    };
+    let enter = |pointer: *const (), containers: &mut Vec<*const ()>| {
+        if containers.contains(&pointer) {
+            return Err(format!(
+                "Cannot copy {}, it contains itself",
+                value.to_type()
+            ));
+        }
+        containers.push(pointer);
+        Ok(())
+    };
+
+    match value {
+        LiteralValue::Nil => Ok(SharedValue::Nil),
+        LiteralValue::True => Ok(SharedValue::Boolean(true)),
+        LiteralValue::False => Ok(SharedValue::Boolean(false)),
+        LiteralValue::Number(x) => Ok(SharedValue::Number(*x)),
+        LiteralValue::StringValue(s) => Ok(SharedValue::String(s.clone())),
+        LiteralValue::Range {
+            start,
+            end,
+            inclusive,
+        } => Ok(SharedValue::Range {
+            start: *start,
+            end: *end,
+            inclusive: *inclusive,
+        }),
+        LiteralValue::List(items) => {
+            enter(Rc::as_ptr(items) as *const (), containers)?;
+            let items = copy_all(&items.borrow(), containers);
+            containers.pop();
+            Ok(SharedValue::List(items?))
+        }
+        LiteralValue::Tuple(items) => Ok(SharedValue::Tuple(copy_all(items, containers)?)),
+        LiteralValue::LoxInstance {
+            class,
+            fields,
+            frozen,
+        } => {
+            enter(Rc::as_ptr(fields) as *const (), containers)?;
+            let mut copied = vec![];
+            for (name, field) in fields.borrow().iter() {
+                match copy_value(field, containers) {
+                    Ok(field) => copied.push((name.clone(), field)),
+                    Err(e) => {
+                        containers.pop();
+                        return Err(e);
+                    }
+                }
+            }
+            containers.pop();
+            Ok(SharedValue::Instance {
+                class: class.name.clone(),
+                fields: copied,
+                frozen: *frozen.borrow(),
+            })
+        }
+        LiteralValue::EnumValue { variant, values } => Ok(SharedValue::EnumValue {
+            enum_name: variant.enum_name.clone(),
+            variant: variant.name.clone(),
+            values: copy_all(values, containers)?,
+        }),
+        other => Err(format!(
+            "Cannot copy {} to another interpreter",
+            other.to_type()
+        )),
+    }
+}
+
+// The source of a script, checked once and run by any number of interpreters. Unlike
+// an interpreter it can be shared between threads, each of which builds its own
+// interpreter from it.
+#[derive(Debug, Clone)]
+pub struct Program {
+    sources: Vec<String>,
+}
+
+impl Program {
+    pub fn compile(source: &str) -> Result<Self, String> {
+        compile(source)?;
+        Ok(Self::from_sources(vec![source.to_string()]))
+    }
+
+    // Sources that were already checked, in the order they ran, like the lines of the REPL
+    pub fn from_sources(sources: Vec<String>) -> Self {
+        Self { sources }
+    }
+
+    pub fn run(&self, interpreter: &mut Interpreter) -> Result<(), String> {
+        for source in &self.sources {
+            crate::run(interpreter, source)?;
+        }
+        Ok(())
+    }
+
+    // An interpreter with the functions, classes, enums and traits of the program, and
+    // the given constants. Its other statements do not run, so global variables are
+    // not shared.
+    pub fn isolate(
+        &self,
+        config: Config,
+        constants: &[(String, SharedValue)],
+    ) -> Result<Interpreter, String> {
+        let mut interpreter = Interpreter::with_config(config);
+        for source in &self.sources {
+            let compiled = compile(source)?;
+            interpreter.resolve(compiled.locals);
+            interpreter.interpret(
+                compiled
+                    .stmts
+                    .iter()
+                    .filter(|stmt| is_declaration(stmt))
+                    .collect(),
+            )?;
+        }
+
+        // After the declarations, so instances of the program's classes can be rebuilt
+        let environment = &interpreter.environment;
+        for (name, value) in constants {
+            let value = value.rebuild(&environment.values.borrow())?;
+            environment.define_const(name.clone(), value)?;
+        }
+        Ok(interpreter)
+    }
+}
+
+// Calls a global function of the interpreter, and waits for it if it is async
+pub fn call_global(
+    interpreter: &Interpreter,
+    name: &str,
+    args: &[SharedValue],
+) -> Result<SharedValue, String> {
+    let globals = interpreter.environment.values.clone();
+    let function = globals.borrow().get(name).cloned();
+    let Some(function) = function else {
+        return Err(format!("Undefined function '{}'", name));
+    };
+
+    let mut arguments = vec![];
+    for arg in args {
+        arguments.push(arg.rebuild(&globals.borrow())?);
+    }
+    let result = call_value(function, arguments, vec![])?;
+    let result = interpreter
+        .environment
+        .runtime
+        .event_loop
+        .block_on(result)?;
+    interpreter.run_tasks()?;
+
+    SharedValue::copy(&result)
+}
+
+// Copies of the global constants, for an isolate to start with. Classes, enums and
+// traits are constants too, but an isolate declares those itself.
+pub fn copy_constants(
+    globals: &HashMap<String, LiteralValue>,
+    constants: &HashSet<String>,
+) -> Result<Vec<(String, SharedValue)>, String> {
+    let mut names: Vec<&String> = constants.iter().collect();
+    names.sort();
+
+    let mut copies = vec![];
+    for name in names {
+        let Some(value) = globals.get(name) else {
+            continue;
+        };
+        let declared = match value {
+            LiteralValue::LoxClass(class) => &class.name == name,
+            LiteralValue::LoxEnum(enum_) => &enum_.name == name,
+            LiteralValue::LoxTrait(trait_) => &trait_.name == name,
+            _ => false,
+        };
+        if !declared {
+            let copy = SharedValue::copy(value)
+                .map_err(|e| format!("Cannot copy constant '{}': {}", name, e))?;
+            copies.push((name.clone(), copy));
+        }
+    }
+    Ok(copies)
+}
+
+fn is_declaration(stmt: &Stmt) -> bool {
+    matches!(
+        stmt,
+        Stmt::Function { .. }
+            | Stmt::CmdFunction { .. }
+            | Stmt::Class { .. }
+            | Stmt::Enum { .. }
+            | Stmt::Trait { .. }
+            | Stmt::Extend { .. }
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_send_sync<T: Send + Sync>() {}
+
+    #[test]
+    fn programs_and_shared_values_cross_threads() {
+        assert_send_sync::<Program>();
+        assert_send_sync::<SharedValue>();
+
+        let program = Program::compile("fun square(x) { return x * x; }").unwrap();
+        let results: Vec<SharedValue> = (1..=3)
+            .map(|i| {
+                let program = program.clone();
+                std::thread::spawn(move || {
+                    let interpreter = program.isolate(Config::default(), &[]).unwrap();
+                    call_global(&interpreter, "square", &[SharedValue::Number(i as f64)])
+                })
+            })
+            .collect::<Vec<_>>()
+            .into_iter()
+            .map(|handle| handle.join().unwrap().unwrap())
+            .collect();
+
+        assert_eq!(
+            results,
+            vec![
+                SharedValue::Number(1.0),
+                SharedValue::Number(4.0),
+                SharedValue::Number(9.0)
+            ]
+        );
+    }
+
+    #[test]
+    fn copies_refuse_cycles_and_functions() {
+        let list = LiteralValue::List(Rc::new(RefCell::new(vec![LiteralValue::Number(1.0)])));
+        let pair = LiteralValue::Tuple(Rc::new(vec![list.clone(), list.clone()]));
+        assert!(SharedValue::copy(&pair).is_ok());
+
+        if let LiteralValue::List(items) = &list {
+            items.borrow_mut().push(list.clone());
+        }
+        assert_eq!(
+            SharedValue::copy(&list).unwrap_err(),
+            "Cannot copy List, it contains itself"
+        );
+
+        let clock = Interpreter::new().environment.values.borrow()["clock"].clone();
+        assert_eq!(
+            SharedValue::copy(&clock).unwrap_err(),
+            "Cannot copy Callable to another interpreter"
+        );
+    }
+}
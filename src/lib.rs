@@ -0,0 +1,91 @@
+pub mod checker;
+pub mod environment;
+pub mod event_loop;
+pub mod expr;
+pub mod generator;
+pub mod interpreter;
+pub mod isolate;
+pub mod natives;
+pub mod parser;
+pub mod printer;
+pub mod resolver;
+pub mod sandbox;
+pub mod scanner;
+pub mod stmt;
+mod tests;
+use crate::checker::*;
+use crate::interpreter::*;
+use crate::parser::*;
+use crate::resolver::*;
+use crate::scanner::*;
+use crate::stmt::Stmt;
+use std::collections::HashMap;
+
+// A program that is ready to run: its statements and the resolved distances of its locals
+pub struct Compiled {
+    pub stmts: Vec<Stmt>,
+    pub locals: HashMap<usize, usize>,
+    pub warnings: Vec<String>,
+}
+
+// Scans, parses, resolves, type checks and runs a program in the given interpreter. Globals
+// defined by earlier runs stay visible, which is what the REPL relies on.
+pub fn run(interpreter: &mut Interpreter, contents: &str) -> Result<(), String> {
+    let Compiled {
+        stmts,
+        locals,
+        warnings,
+    } = compile(contents)?;
+    for warning in warnings {
+        interpreter.environment.runtime.warn(&warning);
+    }
+
+    // Threads started by the program rebuild it from its source
+    interpreter.environment.runtime.add_source(contents);
+    interpreter.resolve(locals);
+
+    interpreter.interpret(stmts.iter().collect())?;
+
+    // Tasks that were started but never awaited still run to the end
+    interpreter.run_tasks()
+}
+
+// Scans, parses, resolves and type checks a program without running it
+pub fn compile(contents: &str) -> Result<Compiled, String> {
+    let mut scanner = Scanner::new(contents);
+    let tokens = scanner.scan_tokens()?;
+
+    let mut parser = Parser::new(tokens);
+    let stmts = parser.parse()?;
+
+    let mut resolver = Resolver::new();
+    let locals = resolver.resolve(&stmts.iter().collect())?;
+
+    Checker::new().check(&stmts.iter().collect::<Vec<_>>())?;
+
+    Ok(Compiled {
+        stmts,
+        locals,
+        warnings: resolver.warnings().to_vec(),
+    })
+}
+
+pub fn dump_tokens(contents: &str) -> Result<String, String> {
+    let mut scanner = Scanner::new(contents);
+    let tokens = scanner.scan_tokens()?;
+
+    Ok(printer::dump_tokens(&tokens))
+}
+
+pub fn dump_ast(contents: &str) -> Result<String, String> {
+    let mut scanner = Scanner::new(contents);
+    let tokens = scanner.scan_tokens()?;
+
+    let mut parser = Parser::new(tokens);
+    let stmts = parser.parse()?;
+
+    let mut resolver = Resolver::new();
+    let locals = resolver.resolve(&stmts.iter().collect())?;
+
+    Ok(printer::dump_ast(&stmts, &locals))
+}
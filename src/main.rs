@@ -1,52 +1,46 @@
-mod environment;
-mod expr;
-mod interpreter;
-mod parser;
-mod resolver;
-mod scanner;
-mod stmt;
-mod tests;
-use crate::interpreter::*;
-use crate::parser::*;
-use crate::resolver::*;
-use crate::scanner::*;
+use cii::interpreter::Interpreter;
+use cii::natives;
+use cii::{dump_ast, dump_tokens, run};
+use cii::sandbox::{self, Config};
 use std::env;
 use std::fs;
 use std::io::{self, BufRead, Write};
 use std::process::exit;
+use std::thread;
 
-pub fn run_file(path: &str) -> Result<(), String> {
+pub fn run_file(path: &str, args: &[String], config: Config) -> Result<(), String> {
     // let mut interpreter = Interpreter::new();
     match fs::read_to_string(path) {
         Err(msg) => return Err(msg.to_string()),
-        Ok(contents) => return run_string(&contents),
+        Ok(contents) => return run_string(&contents, args, config),
     }
 }
 
-pub fn run_string(contents: &str) -> Result<(), String> {
-    let mut interpreter = Interpreter::new();
+pub fn run_string(contents: &str, args: &[String], config: Config) -> Result<(), String> {
+    let mut interpreter = Interpreter::with_config(config);
+    interpreter
+        .environment
+        .define("args".to_string(), natives::process::args_list(args));
 
     run(&mut interpreter, contents)
 }
 
-fn run(interpreter: &mut Interpreter, contents: &str) -> Result<(), String> {
-    let mut scanner = Scanner::new(contents);
-    let tokens = scanner.scan_tokens()?;
-
-    let mut parser = Parser::new(tokens);
-    let stmts = parser.parse()?;
-
-    let resolver = Resolver::new();
-    let locals = resolver.resolve(&stmts.iter().collect())?;
-
-    interpreter.resolve(locals);
-
-    interpreter.interpret(stmts.iter().collect())?;
-    return Ok(());
+// A script that called `exit(n)` stops with status n, any other error with 1
+fn exit_with(result: Result<(), String>) -> ! {
+    match result {
+        Ok(_) => exit(0),
+        Err(msg) => match natives::process::take_exit_status() {
+            Some(code) => exit(code),
+            None => {
+                println!("ERROR:\n{}", msg);
+                exit(1);
+            }
+        },
+    }
 }
 
-fn run_prompt() -> Result<(), String> {
-    let mut interpreter = Interpreter::new();
+fn run_prompt(config: Config) -> Result<(), String> {
+    let mut interpreter = Interpreter::with_config(config);
     loop {
         print!("> ");
         match io::stdout().flush() {
@@ -72,32 +66,66 @@ fn run_prompt() -> Result<(), String> {
         println!("ECHO: {}", buffer);
         match run(&mut interpreter, &buffer) {
             Ok(_) => (),
-            Err(msg) => println!("{}", msg),
+            Err(msg) => {
+                if let Some(code) = natives::process::take_exit_status() {
+                    exit(code);
+                }
+                println!("{}", msg)
+            }
         }
     }
 }
 
 fn main() {
-    let args: Vec<String> = env::args().collect();
+    // Scripts run on their own thread, the main thread's stack is too small
+    // for deeply recursive scripts to reach the call depth limit. --sandbox
+    // only lowers that limit, so this stack is big enough for it too.
+    let runner = thread::Builder::new()
+        .stack_size(sandbox::stack_size(&Config::default()))
+        .spawn(run_main)
+        .expect("Could not start interpreter thread");
 
-    if args.len() == 2 {
-        match run_file(&args[1]) {
-            Ok(_) => exit(0),
-            Err(msg) => {
-                println!("ERROR:\n{}", msg);
-                exit(1);
-            }
-        }
-    } else if args.len() == 3 && args[1] == "e" {
-        match run_string(&args[2]) {
-            Ok(_) => exit(0),
-            Err(msg) => {
-                println!("ERROR:\n{msg}");
-                exit(1);
-            }
+    if runner.join().is_err() {
+        exit(101);
+    }
+}
+
+// What --dump-tokens and --dump-ast print for a source, instead of running it
+type Dump = fn(&str) -> Result<String, String>;
+
+fn run_main() {
+    let mut args: Vec<String> = env::args().collect();
+
+    let mut config = Config::default();
+    let mut dump: Option<Dump> = None;
+    while args.len() >= 2 && args[1].starts_with("--") {
+        match args.remove(1).as_str() {
+            // Run without commands or OS natives and with step and depth limits
+            "--sandbox" => config = Config::sandboxed(),
+            // Print the tokens or the resolved tree instead of running the script
+            "--dump-tokens" => dump = Some(dump_tokens),
+            "--dump-ast" => dump = Some(dump_ast),
+            _ => usage(),
         }
+    }
+
+    if let Some(dump) = dump {
+        let source = if args.len() >= 3 && args[1] == "e" {
+            Ok(args[2].clone())
+        } else if args.len() >= 2 {
+            fs::read_to_string(&args[1]).map_err(|e| e.to_string())
+        } else {
+            usage()
+        };
+        exit_with(source.and_then(|source| dump(&source)).map(|out| print!("{}", out)));
+    }
+
+    if args.len() >= 3 && args[1] == "e" {
+        exit_with(run_string(&args[2], &args[3..], config));
+    } else if args.len() >= 2 && args[1] != "e" {
+        exit_with(run_file(&args[1], &args[2..], config));
     } else if args.len() == 1 {
-        match run_prompt() {
+        match run_prompt(config) {
             Ok(_) => exit(0),
             Err(msg) => {
                 println!("ERROR\n{}", msg);
@@ -105,7 +133,11 @@ fn main() {
             }
         }
     } else {
-        println!("Usage: jlox [script]");
-        exit(64);
+        usage();
     }
 }
+
+fn usage() -> ! {
+    println!("Usage: cii [--sandbox] [--dump-tokens | --dump-ast] [script [args...]]");
+    exit(64);
+}
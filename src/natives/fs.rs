@@ -0,0 +1,216 @@
+use crate::expr::LiteralValue;
+use crate::natives::{namespace, native, record, string_arg, variadic};
+use std::cell::RefCell;
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+use std::rc::Rc;
+
+pub fn fs_namespace() -> LiteralValue {
+    namespace(
+        "fs",
+        vec![
+            ("read_file", native("fs.read_file", 1, read_file_impl)),
+            ("write_file", native("fs.write_file", 2, write_file_impl)),
+            ("append_file", native("fs.append_file", 2, append_file_impl)),
+            ("exists", native("fs.exists", 1, exists_impl)),
+            ("list_dir", native("fs.list_dir", 1, list_dir_impl)),
+            ("mkdir", native("fs.mkdir", 1, mkdir_impl)),
+            ("remove", native("fs.remove", 1, remove_impl)),
+            ("rename", native("fs.rename", 2, rename_impl)),
+            ("stat", native("fs.stat", 1, stat_impl)),
+        ],
+    )
+}
+
+pub fn path_namespace() -> LiteralValue {
+    namespace(
+        "path",
+        vec![
+            ("join", variadic("path.join", 1, join_impl)),
+            ("basename", native("path.basename", 1, basename_impl)),
+            ("dirname", native("path.dirname", 1, dirname_impl)),
+            ("extension", native("path.extension", 1, extension_impl)),
+        ],
+    )
+}
+
+fn read_file_impl(args: &[LiteralValue]) -> Result<LiteralValue, String> {
+    let path = string_arg(args, 0, "fs.read_file")?;
+    match fs::read_to_string(path) {
+        Ok(contents) => Ok(LiteralValue::StringValue(contents)),
+        Err(e) => Err(format!("fs.read_file: could not read '{}': {}", path, e)),
+    }
+}
+
+fn write_file_impl(args: &[LiteralValue]) -> Result<LiteralValue, String> {
+    let path = string_arg(args, 0, "fs.write_file")?;
+    let contents = string_arg(args, 1, "fs.write_file")?;
+    match fs::write(path, contents) {
+        Ok(_) => Ok(LiteralValue::Nil),
+        Err(e) => Err(format!("fs.write_file: could not write '{}': {}", path, e)),
+    }
+}
+
+fn append_file_impl(args: &[LiteralValue]) -> Result<LiteralValue, String> {
+    let path = string_arg(args, 0, "fs.append_file")?;
+    let contents = string_arg(args, 1, "fs.append_file")?;
+    let result = fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .and_then(|mut file| file.write_all(contents.as_bytes()));
+    match result {
+        Ok(_) => Ok(LiteralValue::Nil),
+        Err(e) => Err(format!("fs.append_file: could not write '{}': {}", path, e)),
+    }
+}
+
+fn exists_impl(args: &[LiteralValue]) -> Result<LiteralValue, String> {
+    let path = string_arg(args, 0, "fs.exists")?;
+    Ok(LiteralValue::from_bool(Path::new(path).exists()))
+}
+
+fn list_dir_impl(args: &[LiteralValue]) -> Result<LiteralValue, String> {
+    let path = string_arg(args, 0, "fs.list_dir")?;
+    let entries = match fs::read_dir(path) {
+        Ok(entries) => entries,
+        Err(e) => return Err(format!("fs.list_dir: could not read '{}': {}", path, e)),
+    };
+
+    let mut names = vec![];
+    for entry in entries {
+        match entry {
+            Ok(entry) => names.push(entry.file_name().to_string_lossy().to_string()),
+            Err(e) => return Err(format!("fs.list_dir: could not read '{}': {}", path, e)),
+        }
+    }
+    names.sort();
+
+    let names = names.into_iter().map(LiteralValue::StringValue).collect();
+    Ok(LiteralValue::List(Rc::new(RefCell::new(names))))
+}
+
+fn mkdir_impl(args: &[LiteralValue]) -> Result<LiteralValue, String> {
+    let path = string_arg(args, 0, "fs.mkdir")?;
+    match fs::create_dir_all(path) {
+        Ok(_) => Ok(LiteralValue::Nil),
+        Err(e) => Err(format!("fs.mkdir: could not create '{}': {}", path, e)),
+    }
+}
+
+fn remove_impl(args: &[LiteralValue]) -> Result<LiteralValue, String> {
+    let path = string_arg(args, 0, "fs.remove")?;
+    let result = if Path::new(path).is_dir() {
+        fs::remove_dir_all(path)
+    } else {
+        fs::remove_file(path)
+    };
+    match result {
+        Ok(_) => Ok(LiteralValue::Nil),
+        Err(e) => Err(format!("fs.remove: could not remove '{}': {}", path, e)),
+    }
+}
+
+fn rename_impl(args: &[LiteralValue]) -> Result<LiteralValue, String> {
+    let from = string_arg(args, 0, "fs.rename")?;
+    let to = string_arg(args, 1, "fs.rename")?;
+    match fs::rename(from, to) {
+        Ok(_) => Ok(LiteralValue::Nil),
+        Err(e) => Err(format!(
+            "fs.rename: could not rename '{}' to '{}': {}",
+            from, to, e
+        )),
+    }
+}
+
+fn stat_impl(args: &[LiteralValue]) -> Result<LiteralValue, String> {
+    let path = string_arg(args, 0, "fs.stat")?;
+    let metadata = match fs::metadata(path) {
+        Ok(metadata) => metadata,
+        Err(e) => return Err(format!("fs.stat: could not stat '{}': {}", path, e)),
+    };
+
+    let modified = match metadata
+        .modified()
+        .ok()
+        .and_then(|time| time.duration_since(std::time::UNIX_EPOCH).ok())
+    {
+        Some(duration) => LiteralValue::Number(duration.as_millis() as f64 / 1000.0),
+        None => LiteralValue::Nil,
+    };
+
+    Ok(record(
+        "Stat",
+        vec![
+            ("size".to_string(), LiteralValue::Number(metadata.len() as f64)),
+            ("is_file".to_string(), LiteralValue::from_bool(metadata.is_file())),
+            ("is_dir".to_string(), LiteralValue::from_bool(metadata.is_dir())),
+            ("modified".to_string(), modified),
+        ],
+    ))
+}
+
+fn join_impl(args: &[LiteralValue]) -> Result<LiteralValue, String> {
+    let mut path = Path::new(string_arg(args, 0, "path.join")?).to_path_buf();
+    for i in 1..args.len() {
+        path.push(string_arg(args, i, "path.join")?);
+    }
+
+    Ok(LiteralValue::StringValue(path.to_string_lossy().to_string()))
+}
+
+fn basename_impl(args: &[LiteralValue]) -> Result<LiteralValue, String> {
+    let path = string_arg(args, 0, "path.basename")?;
+    match Path::new(path).file_name() {
+        Some(name) => Ok(LiteralValue::StringValue(name.to_string_lossy().to_string())),
+        None => Ok(LiteralValue::StringValue("".to_string())),
+    }
+}
+
+fn dirname_impl(args: &[LiteralValue]) -> Result<LiteralValue, String> {
+    let path = string_arg(args, 0, "path.dirname")?;
+    match Path::new(path).parent() {
+        Some(parent) => Ok(LiteralValue::StringValue(parent.to_string_lossy().to_string())),
+        None => Ok(LiteralValue::StringValue("".to_string())),
+    }
+}
+
+fn extension_impl(args: &[LiteralValue]) -> Result<LiteralValue, String> {
+    let path = string_arg(args, 0, "path.extension")?;
+    match Path::new(path).extension() {
+        Some(extension) => Ok(LiteralValue::StringValue(
+            extension.to_string_lossy().to_string(),
+        )),
+        None => Ok(LiteralValue::Nil),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn string(s: &str) -> LiteralValue {
+        LiteralValue::StringValue(s.to_string())
+    }
+
+    #[test]
+    fn path_helpers() {
+        let joined = join_impl(&[string("a"), string("b"), string("c.txt")]).unwrap();
+        assert_eq!(joined, string("a/b/c.txt"));
+
+        assert_eq!(basename_impl(&[string("a/b/c.txt")]).unwrap(), string("c.txt"));
+        assert_eq!(dirname_impl(&[string("a/b/c.txt")]).unwrap(), string("a/b"));
+        assert_eq!(extension_impl(&[string("a/b/c.txt")]).unwrap(), string("txt"));
+        assert_eq!(extension_impl(&[string("a/b/c")]).unwrap(), LiteralValue::Nil);
+    }
+
+    #[test]
+    fn errors_instead_of_panics() {
+        let missing = string("/this/path/does/not/exist");
+        assert!(read_file_impl(std::slice::from_ref(&missing)).is_err());
+        assert!(list_dir_impl(std::slice::from_ref(&missing)).is_err());
+        assert!(stat_impl(&[missing]).is_err());
+        assert!(read_file_impl(&[LiteralValue::Number(1.0)]).is_err());
+    }
+}
@@ -0,0 +1,98 @@
+use crate::expr::LiteralValue;
+use crate::generator::Iteration;
+use crate::natives::closure;
+use crate::sandbox::Runtime;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+// collect() and take() step through their items, so they need the runtime to
+// charge those steps to
+pub fn register(env: &mut HashMap<String, LiteralValue>, runtime: &Rc<Runtime>) {
+    let collect_runtime = runtime.clone();
+    env.insert(
+        "collect".to_string(),
+        closure("collect", 1, move |args| {
+            collect_impl(&collect_runtime, args)
+        }),
+    );
+    let take_runtime = runtime.clone();
+    env.insert(
+        "take".to_string(),
+        closure("take", 2, move |args| take_impl(&take_runtime, args)),
+    );
+}
+
+fn list(items: Vec<LiteralValue>) -> LiteralValue {
+    LiteralValue::List(Rc::new(RefCell::new(items)))
+}
+
+// Everything a for-in loop would run over, as a list
+pub fn collect_impl(runtime: &Runtime, args: &[LiteralValue]) -> Result<LiteralValue, String> {
+    let mut iteration = Iteration::new(&args[0]).map_err(|e| format!("collect: {e}"))?;
+    let mut items = vec![];
+    while let Some(item) = iteration.next_item(runtime)? {
+        items.push(item);
+    }
+    Ok(list(items))
+}
+
+// At most the first n values, so it also works on generators that never finish
+pub fn take_impl(runtime: &Runtime, args: &[LiteralValue]) -> Result<LiteralValue, String> {
+    let n = match &args[1] {
+        LiteralValue::Number(n) if n.fract() == 0.0 && *n >= 0.0 => *n as usize,
+        other => {
+            return Err(format!(
+                "take expected a whole number as argument 2 but got {}",
+                other.to_string()
+            ))
+        }
+    };
+
+    let mut iteration = Iteration::new(&args[0]).map_err(|e| format!("take: {e}"))?;
+    let mut items = vec![];
+    while items.len() < n {
+        match iteration.next_item(runtime)? {
+            Some(item) => items.push(item),
+            None => break,
+        }
+    }
+    Ok(list(items))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sandbox::Config;
+
+    #[test]
+    fn take_stops_early() {
+        let runtime = Runtime::new(Config::default());
+        let range = LiteralValue::Range {
+            start: 0.0,
+            end: 1e12,
+            inclusive: false,
+        };
+        let taken = take_impl(&runtime, &[range, LiteralValue::Number(3.0)]).unwrap();
+        assert_eq!(taken.to_string(), "[0, 1, 2]");
+
+        let chars = collect_impl(&runtime, &[LiteralValue::StringValue("ab".to_string())]).unwrap();
+        assert_eq!(chars.to_string(), "[\"a\", \"b\"]");
+        assert!(collect_impl(&runtime, &[LiteralValue::Nil]).is_err());
+    }
+
+    #[test]
+    fn collect_counts_steps() {
+        let mut config = Config::sandboxed();
+        config.max_steps = Some(100);
+        let range = LiteralValue::Range {
+            start: 0.0,
+            end: 1e12,
+            inclusive: false,
+        };
+        assert_eq!(
+            collect_impl(&Runtime::new(config), &[range]).unwrap_err(),
+            "Step budget of 100 exceeded"
+        );
+    }
+}
@@ -0,0 +1,461 @@
+use crate::expr::LiteralValue;
+use crate::natives::{record, string_arg};
+use std::cell::RefCell;
+use std::rc::Rc;
+
+// Deeper nesting than this is refused instead of overflowing the stack
+const MAX_DEPTH: usize = 512;
+
+pub fn json_parse_impl(args: &[LiteralValue]) -> Result<LiteralValue, String> {
+    let source = string_arg(args, 0, "json_parse")?;
+    match JsonParser::new(source).parse() {
+        Ok(value) => Ok(value),
+        Err(msg) => Err(format!("json_parse: {}", msg)),
+    }
+}
+
+pub fn json_stringify_impl(args: &[LiteralValue]) -> Result<LiteralValue, String> {
+    if args.len() > 2 {
+        return Err(format!(
+            "Callable json_stringify expected 1 to 2 arguments but got {}",
+            args.len()
+        ));
+    }
+    let indent = match args.get(1) {
+        None | Some(LiteralValue::Nil) => None,
+        Some(LiteralValue::Number(n)) if *n >= 0.0 => Some(" ".repeat(*n as usize)),
+        Some(LiteralValue::StringValue(s)) => Some(s.clone()),
+        Some(other) => {
+            return Err(format!(
+                "json_stringify expected a Number or String as indent but got {}",
+                other.to_type()
+            ))
+        }
+    };
+
+    let mut out = String::new();
+    stringify(&args[0], indent.as_deref(), 0, &mut out)?;
+    Ok(LiteralValue::StringValue(out))
+}
+
+fn stringify(
+    value: &LiteralValue,
+    indent: Option<&str>,
+    depth: usize,
+    out: &mut String,
+) -> Result<(), String> {
+    if depth > MAX_DEPTH {
+        return Err("json_stringify: value is nested too deeply (or contains itself)".to_string());
+    }
+
+    match value {
+        LiteralValue::Number(x) => {
+            if !x.is_finite() {
+                return Err(format!("json_stringify: cannot represent {} in JSON", x));
+            }
+            out.push_str(&x.to_string());
+        }
+        LiteralValue::StringValue(s) => write_string(s, out),
+        LiteralValue::True => out.push_str("true"),
+        LiteralValue::False => out.push_str("false"),
+        LiteralValue::Nil => out.push_str("null"),
+        LiteralValue::List(items) => {
+            let items = items.borrow();
+            out.push('[');
+            for (i, item) in items.iter().enumerate() {
+                if i > 0 {
+                    out.push(',');
+                }
+                newline(indent, depth + 1, out);
+                stringify(item, indent, depth + 1, out)?;
+            }
+            if !items.is_empty() {
+                newline(indent, depth, out);
+            }
+            out.push(']');
+        }
+        LiteralValue::LoxInstance { fields, .. } => {
+            let fields = fields.borrow();
+            out.push('{');
+            for (i, (name, field)) in fields.iter().enumerate() {
+                if i > 0 {
+                    out.push(',');
+                }
+                newline(indent, depth + 1, out);
+                write_string(name, out);
+                out.push(':');
+                if indent.is_some() {
+                    out.push(' ');
+                }
+                stringify(field, indent, depth + 1, out)?;
+            }
+            if !fields.is_empty() {
+                newline(indent, depth, out);
+            }
+            out.push('}');
+        }
+        other => {
+            return Err(format!(
+                "json_stringify: cannot convert {} to JSON",
+                other.to_type()
+            ))
+        }
+    }
+
+    Ok(())
+}
+
+fn newline(indent: Option<&str>, depth: usize, out: &mut String) {
+    if let Some(indent) = indent {
+        out.push('\n');
+        for _ in 0..depth {
+            out.push_str(indent);
+        }
+    }
+}
+
+fn write_string(s: &str, out: &mut String) {
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
+
+struct JsonParser {
+    chars: Vec<char>,
+    current: usize,
+    line: usize,
+    column: usize,
+    depth: usize,
+}
+
+impl JsonParser {
+    fn new(source: &str) -> Self {
+        Self {
+            chars: source.chars().collect(),
+            current: 0,
+            line: 1,
+            column: 1,
+            depth: 0,
+        }
+    }
+
+    fn parse(&mut self) -> Result<LiteralValue, String> {
+        let value = self.value()?;
+        self.skip_whitespace();
+        if self.peek().is_some() {
+            return Err(self.error("Unexpected trailing characters"));
+        }
+
+        Ok(value)
+    }
+
+    fn value(&mut self) -> Result<LiteralValue, String> {
+        self.skip_whitespace();
+        match self.peek() {
+            Some('{') => self.nested(Self::object),
+            Some('[') => self.nested(Self::array),
+            Some('"') => Ok(LiteralValue::StringValue(self.string()?)),
+            Some('t') => self.keyword("true", LiteralValue::True),
+            Some('f') => self.keyword("false", LiteralValue::False),
+            Some('n') => self.keyword("null", LiteralValue::Nil),
+            Some(c) if c == '-' || c.is_ascii_digit() => self.number(),
+            Some(c) => Err(self.error(&format!("Unexpected character '{}'", c))),
+            None => Err(self.error("Unexpected end of input")),
+        }
+    }
+
+    fn nested(
+        &mut self,
+        parse: fn(&mut Self) -> Result<LiteralValue, String>,
+    ) -> Result<LiteralValue, String> {
+        if self.depth >= MAX_DEPTH {
+            return Err(self.error("Nesting is too deep"));
+        }
+        self.depth += 1;
+        let result = parse(self);
+        self.depth -= 1;
+        result
+    }
+
+    fn object(&mut self) -> Result<LiteralValue, String> {
+        self.advance(); // {
+        let mut fields: Vec<(String, LiteralValue)> = vec![];
+
+        self.skip_whitespace();
+        if self.peek() == Some('}') {
+            self.advance();
+            return Ok(record("Object", fields));
+        }
+
+        loop {
+            self.skip_whitespace();
+            if self.peek() != Some('"') {
+                return Err(self.error("Expected string key"));
+            }
+            let key = self.string()?;
+            self.skip_whitespace();
+            self.expect(':')?;
+            let value = self.value()?;
+
+            // Later duplicates win, like in most JSON parsers
+            match fields.iter_mut().find(|(name, _)| name == &key) {
+                Some(field) => field.1 = value,
+                None => fields.push((key, value)),
+            }
+
+            self.skip_whitespace();
+            match self.advance() {
+                Some(',') => continue,
+                Some('}') => break,
+                _ => return Err(self.error("Expected ',' or '}' in object")),
+            }
+        }
+
+        Ok(record("Object", fields))
+    }
+
+    fn array(&mut self) -> Result<LiteralValue, String> {
+        self.advance(); // [
+        let mut items = vec![];
+
+        self.skip_whitespace();
+        if self.peek() == Some(']') {
+            self.advance();
+            return Ok(LiteralValue::List(Rc::new(RefCell::new(items))));
+        }
+
+        loop {
+            items.push(self.value()?);
+            self.skip_whitespace();
+            match self.advance() {
+                Some(',') => continue,
+                Some(']') => break,
+                _ => return Err(self.error("Expected ',' or ']' in array")),
+            }
+        }
+
+        Ok(LiteralValue::List(Rc::new(RefCell::new(items))))
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        self.advance(); // "
+        let mut result = String::new();
+        loop {
+            match self.advance() {
+                None => return Err(self.error("Unterminated string")),
+                Some('"') => return Ok(result),
+                Some('\\') => match self.advance() {
+                    Some('"') => result.push('"'),
+                    Some('\\') => result.push('\\'),
+                    Some('/') => result.push('/'),
+                    Some('b') => result.push('\u{8}'),
+                    Some('f') => result.push('\u{c}'),
+                    Some('n') => result.push('\n'),
+                    Some('r') => result.push('\r'),
+                    Some('t') => result.push('\t'),
+                    Some('u') => result.push(self.unicode_escape()?),
+                    _ => return Err(self.error("Invalid escape sequence")),
+                },
+                Some(c) if (c as u32) < 0x20 => {
+                    return Err(self.error("Control character in string"))
+                }
+                Some(c) => result.push(c),
+            }
+        }
+    }
+
+    fn unicode_escape(&mut self) -> Result<char, String> {
+        let high = self.hex4()?;
+        if !(0xD800..0xDC00).contains(&high) {
+            return char::from_u32(high).ok_or_else(|| self.error("Invalid unicode escape"));
+        }
+
+        // Surrogate pair
+        if self.advance() != Some('\\') || self.advance() != Some('u') {
+            return Err(self.error("Expected low surrogate after high surrogate"));
+        }
+        let low = self.hex4()?;
+        if !(0xDC00..0xE000).contains(&low) {
+            return Err(self.error("Invalid low surrogate"));
+        }
+        let code = 0x10000 + ((high - 0xD800) << 10) + (low - 0xDC00);
+        char::from_u32(code).ok_or_else(|| self.error("Invalid unicode escape"))
+    }
+
+    fn hex4(&mut self) -> Result<u32, String> {
+        let mut code = 0;
+        for _ in 0..4 {
+            match self.advance().and_then(|c| c.to_digit(16)) {
+                Some(digit) => code = code * 16 + digit,
+                None => return Err(self.error("Invalid unicode escape")),
+            }
+        }
+        Ok(code)
+    }
+
+    fn number(&mut self) -> Result<LiteralValue, String> {
+        let (line, column) = (self.line, self.column);
+        let start = self.current;
+
+        if self.peek() == Some('-') {
+            self.advance();
+        }
+        match self.peek() {
+            Some('0') => {
+                self.advance();
+            }
+            Some(c) if c.is_ascii_digit() => self.digits(),
+            _ => return Err(self.error("Expected digit")),
+        }
+        if self.peek() == Some('.') {
+            self.advance();
+            if !self.peek().is_some_and(|c| c.is_ascii_digit()) {
+                return Err(self.error("Expected digit after '.'"));
+            }
+            self.digits();
+        }
+        if let Some('e') | Some('E') = self.peek() {
+            self.advance();
+            if let Some('+') | Some('-') = self.peek() {
+                self.advance();
+            }
+            if !self.peek().is_some_and(|c| c.is_ascii_digit()) {
+                return Err(self.error("Expected digit in exponent"));
+            }
+            self.digits();
+        }
+
+        let text: String = self.chars[start..self.current].iter().collect();
+        match text.parse::<f64>() {
+            Ok(x) => Ok(LiteralValue::Number(x)),
+            Err(_) => Err(format!(
+                "Invalid number '{}' at line {}, column {}",
+                text, line, column
+            )),
+        }
+    }
+
+    fn digits(&mut self) {
+        while self.peek().is_some_and(|c| c.is_ascii_digit()) {
+            self.advance();
+        }
+    }
+
+    fn keyword(&mut self, word: &str, value: LiteralValue) -> Result<LiteralValue, String> {
+        for expected in word.chars() {
+            if self.peek() != Some(expected) {
+                return Err(self.error(&format!("Invalid literal, expected '{}'", word)));
+            }
+            self.advance();
+        }
+        Ok(value)
+    }
+
+    fn expect(&mut self, expected: char) -> Result<(), String> {
+        if self.peek() == Some(expected) {
+            self.advance();
+            Ok(())
+        } else {
+            Err(self.error(&format!("Expected '{}'", expected)))
+        }
+    }
+
+    fn skip_whitespace(&mut self) {
+        while let Some(' ' | '\t' | '\n' | '\r') = self.peek() {
+            self.advance();
+        }
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.current).copied()
+    }
+
+    fn advance(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.current += 1;
+        if c == '\n' {
+            self.line += 1;
+            self.column = 1;
+        } else {
+            self.column += 1;
+        }
+        Some(c)
+    }
+
+    fn error(&self, msg: &str) -> String {
+        format!("{} at line {}, column {}", msg, self.line, self.column)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(source: &str) -> Result<LiteralValue, String> {
+        json_parse_impl(&[LiteralValue::StringValue(source.to_string())])
+    }
+
+    fn stringify(value: LiteralValue) -> String {
+        match json_stringify_impl(&[value]).unwrap() {
+            LiteralValue::StringValue(s) => s,
+            _ => panic!("json_stringify did not return a string"),
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let source = r#"{"a":[1,2.5,-3e2,true,false,null],"b":"x\"y\n\u00e9\ud83d\ude00","c":{}}"#;
+        let value = parse(source).unwrap();
+        assert_eq!(
+            stringify(value),
+            "{\"a\":[1,2.5,-300,true,false,null],\"b\":\"x\\\"y\\né😀\",\"c\":{}}"
+        );
+    }
+
+    #[test]
+    fn malformed_input_reports_position() {
+        assert_eq!(
+            parse("{\"a\": 1,\n  \"b\" 2}").unwrap_err(),
+            "json_parse: Expected ':' at line 2, column 7"
+        );
+        assert_eq!(
+            parse("[1, 2").unwrap_err(),
+            "json_parse: Expected ',' or ']' in array at line 1, column 6"
+        );
+        assert!(parse("01").is_err());
+        assert!(parse("\"abc").is_err());
+        assert!(parse("tru").is_err());
+        assert!(parse("\"\\ud800\"").is_err());
+        assert!(parse("").is_err());
+    }
+
+    #[test]
+    fn deep_nesting_is_an_error() {
+        let source = "[".repeat(100_000);
+        assert!(parse(&source).unwrap_err().contains("Nesting is too deep"));
+    }
+
+    #[test]
+    fn self_containing_list_is_an_error() {
+        let list = LiteralValue::List(Rc::new(RefCell::new(vec![])));
+        if let LiteralValue::List(items) = &list {
+            items.borrow_mut().push(list.clone());
+        }
+        assert!(json_stringify_impl(std::slice::from_ref(&list)).is_err());
+
+        // Break the cycle so the list is freed
+        if let LiteralValue::List(items) = &list {
+            items.borrow_mut().clear();
+        }
+    }
+}
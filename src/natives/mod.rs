@@ -0,0 +1,187 @@
+use crate::expr::{CallableImpl, LiteralValue, LoxClassImpl, NativeFunctionImpl};
+use crate::sandbox::{denied_message, Capability, Config};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+pub mod fs;
+pub mod iter;
+pub mod json;
+pub mod process;
+pub mod reflect;
+pub mod tasks;
+pub mod threads;
+
+pub type NativeFn = fn(&[LiteralValue]) -> Result<LiteralValue, String>;
+
+pub fn native(name: &str, arity: usize, fun: NativeFn) -> LiteralValue {
+    LiteralValue::Callable(CallableImpl::NativeFunction(NativeFunctionImpl {
+        name: name.to_string(),
+        arity,
+        variadic: false,
+        fun: Rc::new(fun),
+    }))
+}
+
+// A native that holds on to some state, like the event loop it schedules on
+pub fn closure(
+    name: &str,
+    arity: usize,
+    fun: impl Fn(&[LiteralValue]) -> Result<LiteralValue, String> + 'static,
+) -> LiteralValue {
+    LiteralValue::Callable(CallableImpl::NativeFunction(NativeFunctionImpl {
+        name: name.to_string(),
+        arity,
+        variadic: false,
+        fun: Rc::new(fun),
+    }))
+}
+
+// Takes at least `arity` arguments, the function checks the rest itself
+pub fn variadic(name: &str, arity: usize, fun: NativeFn) -> LiteralValue {
+    LiteralValue::Callable(CallableImpl::NativeFunction(NativeFunctionImpl {
+        name: name.to_string(),
+        arity,
+        variadic: true,
+        fun: Rc::new(fun),
+    }))
+}
+
+// Groups natives under a name, so they are called as `name.member(...)`
+pub fn namespace(name: &str, members: Vec<(&str, LiteralValue)>) -> LiteralValue {
+    let members = members
+        .into_iter()
+        .map(|(member, value)| (member.to_string(), value))
+        .collect::<HashMap<String, LiteralValue>>();
+
+    LiteralValue::Namespace {
+        name: name.to_string(),
+        members: Rc::new(members),
+    }
+}
+
+// A plain instance without methods, used to hand structured data to scripts
+pub fn record(class_name: &str, fields: Vec<(String, LiteralValue)>) -> LiteralValue {
+    let class = LoxClassImpl::new(class_name.to_string(), HashMap::new(), None, vec![]);
+
+    LiteralValue::LoxInstance {
+        class: Rc::new(class),
+        fields: Rc::new(RefCell::new(fields.into_iter().collect())),
+        frozen: Rc::new(RefCell::new(false)),
+    }
+}
+
+pub fn string_arg<'a>(args: &'a [LiteralValue], i: usize, fun: &str) -> Result<&'a str, String> {
+    match args.get(i) {
+        Some(LiteralValue::StringValue(s)) => Ok(s),
+        Some(other) => Err(format!(
+            "{fun} expected a String as argument {} but got {}",
+            i + 1,
+            other.to_type()
+        )),
+        None => Err(format!("{fun} is missing argument {}", i + 1)),
+    }
+}
+
+pub fn register(env: &mut HashMap<String, LiteralValue>) {
+    env.insert("fs".to_string(), fs::fs_namespace());
+    env.insert("path".to_string(), fs::path_namespace());
+    env.insert(
+        "json_parse".to_string(),
+        native("json_parse", 1, json::json_parse_impl),
+    );
+    env.insert(
+        "json_stringify".to_string(),
+        variadic("json_stringify", 1, json::json_stringify_impl),
+    );
+
+    // `args` is replaced by main with the script's own arguments
+    env.insert("args".to_string(), process::args_list(&[]));
+    env.insert(
+        "getenv".to_string(),
+        native("getenv", 1, process::getenv_impl),
+    );
+    env.insert(
+        "setenv".to_string(),
+        native("setenv", 2, process::setenv_impl),
+    );
+    env.insert(
+        "unsetenv".to_string(),
+        native("unsetenv", 1, process::unsetenv_impl),
+    );
+    env.insert("cwd".to_string(), native("cwd", 0, process::cwd_impl));
+    env.insert("chdir".to_string(), native("chdir", 1, process::chdir_impl));
+    env.insert("exit".to_string(), variadic("exit", 0, process::exit_impl));
+
+    env.insert(
+        "has_field".to_string(),
+        native("has_field", 2, reflect::has_field_impl),
+    );
+    env.insert(
+        "get_field".to_string(),
+        native("get_field", 2, reflect::get_field_impl),
+    );
+    env.insert(
+        "set_field".to_string(),
+        native("set_field", 3, reflect::set_field_impl),
+    );
+    env.insert("fields".to_string(), native("fields", 1, reflect::fields_impl));
+    env.insert(
+        "class_of".to_string(),
+        native("class_of", 1, reflect::class_of_impl),
+    );
+    env.insert(
+        "instance_of".to_string(),
+        native("instance_of", 2, reflect::instance_of_impl),
+    );
+}
+
+// Globals that reach outside the interpreter, with the capability they need
+const GUARDED: &[(&str, Capability)] = &[
+    ("fs", Capability::FileSystem),
+    ("getenv", Capability::Environment),
+    ("setenv", Capability::Environment),
+    ("unsetenv", Capability::Environment),
+    ("cwd", Capability::Process),
+    ("chdir", Capability::Process),
+    ("exit", Capability::Process),
+    ("thread_spawn", Capability::Threads),
+];
+
+// Replaces the natives the config does not allow with ones that raise an error
+pub fn restrict(env: &mut HashMap<String, LiteralValue>, config: &Config) {
+    for (name, capability) in GUARDED {
+        if let Some(value) = env.get(*name) {
+            let restricted = deny(value, *capability, config);
+            env.insert(name.to_string(), restricted);
+        }
+    }
+}
+
+fn deny(value: &LiteralValue, capability: Capability, config: &Config) -> LiteralValue {
+    match value {
+        LiteralValue::Callable(CallableImpl::NativeFunction(nativefun))
+            if !config.allows_native(&nativefun.name, capability) =>
+        {
+            let message = denied_message(&nativefun.name, capability);
+            LiteralValue::Callable(CallableImpl::NativeFunction(NativeFunctionImpl {
+                name: nativefun.name.clone(),
+                arity: 0,
+                variadic: true,
+                fun: Rc::new(move |_args: &[LiteralValue]| Err(message.clone())),
+            }))
+        }
+        LiteralValue::Namespace { name, members } => {
+            let members = members
+                .iter()
+                .map(|(member, value)| (member.clone(), deny(value, capability, config)))
+                .collect::<HashMap<String, LiteralValue>>();
+
+            LiteralValue::Namespace {
+                name: name.clone(),
+                members: Rc::new(members),
+            }
+        }
+        other => other.clone(),
+    }
+}
@@ -0,0 +1,139 @@
+use crate::expr::LiteralValue;
+use crate::natives::string_arg;
+use std::cell::{Cell, RefCell};
+use std::env;
+use std::io::{self, Write};
+use std::rc::Rc;
+
+thread_local! {
+    // Set by `exit(code)`, the error it raises unwinds the script back to the caller
+    static EXIT_STATUS: Cell<Option<i32>> = const { Cell::new(None) };
+}
+
+// Returns the code passed to `exit` if that is what stopped the script
+pub fn take_exit_status() -> Option<i32> {
+    EXIT_STATUS.with(|status| status.take())
+}
+
+pub fn args_list(args: &[String]) -> LiteralValue {
+    let args = args
+        .iter()
+        .map(|arg| LiteralValue::StringValue(arg.clone()))
+        .collect();
+    LiteralValue::List(Rc::new(RefCell::new(args)))
+}
+
+pub fn getenv_impl(args: &[LiteralValue]) -> Result<LiteralValue, String> {
+    let name = string_arg(args, 0, "getenv")?;
+    match env::var(name) {
+        Ok(value) => Ok(LiteralValue::StringValue(value)),
+        Err(_) => Ok(LiteralValue::Nil),
+    }
+}
+
+pub fn setenv_impl(args: &[LiteralValue]) -> Result<LiteralValue, String> {
+    let name = string_arg(args, 0, "setenv")?;
+    let value = string_arg(args, 1, "setenv")?;
+    if name.is_empty() || name.contains('=') || name.contains('\0') || value.contains('\0') {
+        return Err(format!("setenv: invalid variable name or value '{}'", name));
+    }
+
+    env::set_var(name, value);
+    Ok(LiteralValue::Nil)
+}
+
+pub fn unsetenv_impl(args: &[LiteralValue]) -> Result<LiteralValue, String> {
+    let name = string_arg(args, 0, "unsetenv")?;
+    if name.is_empty() || name.contains('=') || name.contains('\0') {
+        return Err(format!("unsetenv: invalid variable name '{}'", name));
+    }
+
+    env::remove_var(name);
+    Ok(LiteralValue::Nil)
+}
+
+pub fn cwd_impl(_args: &[LiteralValue]) -> Result<LiteralValue, String> {
+    match env::current_dir() {
+        Ok(dir) => Ok(LiteralValue::StringValue(dir.to_string_lossy().to_string())),
+        Err(e) => Err(format!("cwd: could not read current directory: {}", e)),
+    }
+}
+
+pub fn chdir_impl(args: &[LiteralValue]) -> Result<LiteralValue, String> {
+    let path = string_arg(args, 0, "chdir")?;
+    match env::set_current_dir(path) {
+        Ok(_) => Ok(LiteralValue::Nil),
+        Err(e) => Err(format!("chdir: could not change to '{}': {}", path, e)),
+    }
+}
+
+pub fn exit_impl(args: &[LiteralValue]) -> Result<LiteralValue, String> {
+    let code = match args {
+        [] => 0,
+        [LiteralValue::Number(x)] if x.fract() == 0.0 => *x as i32,
+        [other] => {
+            return Err(format!(
+                "exit expected a whole Number as status but got {}",
+                other.to_string()
+            ))
+        }
+        _ => {
+            return Err(format!(
+                "Callable exit expected 0 to 1 arguments but got {}",
+                args.len()
+            ))
+        }
+    };
+
+    Err(exit_error(code))
+}
+
+// Records the status and returns the error that unwinds the script, for `exit` and
+// for threads that exited when they are joined
+pub fn exit_error(code: i32) -> String {
+    let _ = io::stdout().flush();
+    EXIT_STATUS.with(|status| status.set(Some(code)));
+    format!("Script exited with status {}", code)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn exit_records_status() {
+        assert!(exit_impl(&[LiteralValue::Number(3.0)]).is_err());
+        assert_eq!(take_exit_status(), Some(3));
+        assert_eq!(take_exit_status(), None);
+
+        assert!(exit_impl(&[]).is_err());
+        assert_eq!(take_exit_status(), Some(0));
+
+        assert!(exit_impl(&[LiteralValue::Number(1.5)]).is_err());
+        assert_eq!(take_exit_status(), None);
+    }
+
+    #[test]
+    fn environment_variables() {
+        let name = LiteralValue::StringValue("CII_PROCESS_TEST_VAR".to_string());
+        let value = LiteralValue::StringValue("42".to_string());
+        assert_eq!(getenv_impl(std::slice::from_ref(&name)).unwrap(), LiteralValue::Nil);
+        setenv_impl(&[name.clone(), value.clone()]).unwrap();
+        assert_eq!(getenv_impl(std::slice::from_ref(&name)).unwrap(), value);
+        unsetenv_impl(std::slice::from_ref(&name)).unwrap();
+        assert_eq!(getenv_impl(&[name]).unwrap(), LiteralValue::Nil);
+
+        let invalid = LiteralValue::StringValue("A=B".to_string());
+        assert!(setenv_impl(&[invalid, value]).is_err());
+    }
+
+    // Without changing the directory, other tests in this process rely on it
+    #[test]
+    fn chdir_to_missing_directory_fails() {
+        let start = cwd_impl(&[]).unwrap();
+        let missing = LiteralValue::StringValue("/cii/surely/missing".to_string());
+        let error = chdir_impl(&[missing]).unwrap_err();
+        assert!(error.starts_with("chdir: could not change to '/cii/surely/missing'"));
+        assert_eq!(cwd_impl(&[]).unwrap(), start);
+    }
+}
@@ -0,0 +1,127 @@
+use crate::expr::{Fields, LiteralValue};
+use crate::natives::string_arg;
+use std::cell::RefCell;
+use std::rc::Rc;
+
+fn instance_fields(args: &[LiteralValue], fun: &str) -> Result<Rc<RefCell<Fields>>, String> {
+    match &args[0] {
+        LiteralValue::LoxInstance { fields, .. } => Ok(fields.clone()),
+        other => Err(format!(
+            "{fun} expected an instance as argument 1 but got {}",
+            other.to_type()
+        )),
+    }
+}
+
+pub fn has_field_impl(args: &[LiteralValue]) -> Result<LiteralValue, String> {
+    let fields = instance_fields(args, "has_field")?;
+    let name = string_arg(args, 1, "has_field")?;
+    let has_field = fields.borrow().contains_key(name);
+    Ok(LiteralValue::from_bool(has_field))
+}
+
+pub fn get_field_impl(args: &[LiteralValue]) -> Result<LiteralValue, String> {
+    let fields = instance_fields(args, "get_field")?;
+    let name = string_arg(args, 1, "get_field")?;
+    let value = fields.borrow().get(name).cloned();
+    value.ok_or_else(|| format!("get_field: no field named {} on this instance", name))
+}
+
+pub fn set_field_impl(args: &[LiteralValue]) -> Result<LiteralValue, String> {
+    let fields = instance_fields(args, "set_field")?;
+    let name = string_arg(args, 1, "set_field")?;
+    if let LiteralValue::LoxInstance { frozen, .. } = &args[0] {
+        if *frozen.borrow() {
+            return Err(format!(
+                "Cannot set property {} on frozen instance of '{}'",
+                name,
+                args[0].to_type()
+            ));
+        }
+    }
+
+    fields
+        .borrow_mut()
+        .insert(name.to_string(), args[2].clone());
+    Ok(LiteralValue::Nil)
+}
+
+// Field names in the order they were first set
+pub fn fields_impl(args: &[LiteralValue]) -> Result<LiteralValue, String> {
+    let fields = instance_fields(args, "fields")?;
+    let names = fields
+        .borrow()
+        .keys()
+        .map(|name| LiteralValue::StringValue(name.clone()))
+        .collect();
+    Ok(LiteralValue::List(Rc::new(RefCell::new(names))))
+}
+
+pub fn class_of_impl(args: &[LiteralValue]) -> Result<LiteralValue, String> {
+    match &args[0] {
+        LiteralValue::LoxInstance { class, .. } => Ok(LiteralValue::LoxClass(class.clone())),
+        other => Err(format!(
+            "class_of expected an instance as argument 1 but got {}",
+            other.to_type()
+        )),
+    }
+}
+
+// True if the value is an instance of the class or of one of its subclasses, or
+// of a class that mixes in the trait
+pub fn instance_of_impl(args: &[LiteralValue]) -> Result<LiteralValue, String> {
+    let class = match &args[0] {
+        LiteralValue::LoxInstance { class, .. } => class,
+        _ => return Ok(LiteralValue::False),
+    };
+
+    match &args[1] {
+        LiteralValue::LoxClass(target) => Ok(LiteralValue::from_bool(class.inherits_from(target))),
+        LiteralValue::LoxTrait(target) => Ok(LiteralValue::from_bool(class.implements(target))),
+        other => Err(format!(
+            "instance_of expected a class or trait as argument 2 but got {}",
+            other.to_type()
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::natives::record;
+
+    #[test]
+    fn fields_are_listed_in_insertion_order() {
+        let point = record(
+            "Point",
+            vec![
+                ("y".to_string(), LiteralValue::Number(2.0)),
+                ("x".to_string(), LiteralValue::Number(1.0)),
+            ],
+        );
+        let name = |s: &str| LiteralValue::StringValue(s.to_string());
+
+        assert_eq!(
+            fields_impl(std::slice::from_ref(&point))
+                .unwrap()
+                .to_string(),
+            "[\"y\", \"x\"]"
+        );
+        assert_eq!(
+            has_field_impl(&[point.clone(), name("x")]).unwrap(),
+            LiteralValue::True
+        );
+        set_field_impl(&[point.clone(), name("z"), LiteralValue::Nil]).unwrap();
+        assert_eq!(
+            get_field_impl(&[point.clone(), name("z")]).unwrap(),
+            LiteralValue::Nil
+        );
+        assert!(get_field_impl(&[point.clone(), name("w")]).is_err());
+        set_field_impl(&[point.clone(), name("y"), LiteralValue::Nil]).unwrap();
+        assert_eq!(
+            fields_impl(&[point]).unwrap().to_string(),
+            "[\"y\", \"x\", \"z\"]"
+        );
+        assert!(fields_impl(&[LiteralValue::Number(1.0)]).is_err());
+    }
+}
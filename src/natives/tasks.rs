@@ -0,0 +1,85 @@
+use crate::event_loop::{Channel, EventLoop};
+use crate::expr::{CallableImpl, LiteralValue};
+use crate::natives::{closure, record};
+use std::collections::HashMap;
+use std::rc::Rc;
+
+pub fn register(env: &mut HashMap<String, LiteralValue>, event_loop: &Rc<EventLoop>) {
+    let spawn_loop = event_loop.clone();
+    env.insert(
+        "spawn".to_string(),
+        closure("spawn", 1, move |args| spawn_impl(&spawn_loop, args)),
+    );
+    let sleep_loop = event_loop.clone();
+    env.insert(
+        "sleep".to_string(),
+        closure("sleep", 1, move |args| sleep_impl(&sleep_loop, args)),
+    );
+    let channel_loop = event_loop.clone();
+    env.insert(
+        "channel".to_string(),
+        closure("channel", 0, move |_args| Ok(channel(&channel_loop))),
+    );
+}
+
+fn spawn_impl(event_loop: &EventLoop, args: &[LiteralValue]) -> Result<LiteralValue, String> {
+    match &args[0] {
+        LiteralValue::Callable(callable) => {
+            let name = match callable {
+                CallableImpl::LoxFunction(fun) => fun.name.clone(),
+                CallableImpl::NativeFunction(fun) => fun.name.clone(),
+            };
+            let task = event_loop.spawn_call(name, args[0].clone());
+            Ok(LiteralValue::Task(task))
+        }
+        other => Err(format!(
+            "spawn expected a function but got {}",
+            other.to_type()
+        )),
+    }
+}
+
+fn sleep_impl(event_loop: &EventLoop, args: &[LiteralValue]) -> Result<LiteralValue, String> {
+    match &args[0] {
+        LiteralValue::Number(millis) if *millis >= 0.0 => {
+            Ok(LiteralValue::Task(event_loop.sleep(*millis as u64)))
+        }
+        other => Err(format!(
+            "sleep expected a Number of milliseconds but got {}",
+            other.to_string()
+        )),
+    }
+}
+
+// An instance with send(value), receive() and close()
+fn channel(event_loop: &Rc<EventLoop>) -> LiteralValue {
+    let channel = Rc::new(Channel::default());
+
+    let (send_loop, send_channel) = (event_loop.clone(), channel.clone());
+    let send = closure("send", 1, move |args| {
+        send_channel.send(&send_loop, args[0].clone())?;
+        Ok(LiteralValue::Nil)
+    });
+    let receive_channel = channel.clone();
+    let receive = closure("receive", 0, move |_args| {
+        Ok(LiteralValue::Task(receive_channel.receive()))
+    });
+    let close_loop = event_loop.clone();
+    let close = closure("close", 0, move |_args| {
+        channel.close(&close_loop)?;
+        Ok(LiteralValue::Nil)
+    });
+
+    let value = record(
+        "Channel",
+        vec![
+            ("send".to_string(), send),
+            ("receive".to_string(), receive),
+            ("close".to_string(), close),
+        ],
+    );
+    if let LiteralValue::LoxInstance { frozen, .. } = &value {
+        *frozen.borrow_mut() = true;
+    }
+    value
+}
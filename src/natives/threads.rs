@@ -0,0 +1,184 @@
+use crate::expr::{CallableImpl, LiteralValue, NativeFunctionImpl};
+use crate::isolate::{call_global, copy_constants, Program, SharedValue};
+use crate::natives::process::{exit_error, take_exit_status};
+use crate::natives::record;
+use crate::sandbox::{stack_size, Runtime};
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::rc::{Rc, Weak};
+use std::thread::{self, JoinHandle};
+
+type Globals = RefCell<HashMap<String, LiteralValue>>;
+type Constants = RefCell<HashSet<String>>;
+
+// What a thread hands back on join: its printed output, if it was captured, its
+// result and the status it called exit() with, if it did
+type Finished = (String, Result<SharedValue, String>, Option<i32>);
+
+// `constants` are the names of the global constants, which threads get a copy of
+pub fn register(globals: &Rc<Globals>, constants: &Rc<Constants>, runtime: &Rc<Runtime>) {
+    let (globals_weak, constants, runtime) =
+        (Rc::downgrade(globals), constants.clone(), runtime.clone());
+    let thread_spawn = LiteralValue::Callable(CallableImpl::NativeFunction(NativeFunctionImpl {
+        name: "thread_spawn".to_string(),
+        arity: 1,
+        variadic: true,
+        fun: Rc::new(move |args: &[LiteralValue]| {
+            thread_spawn_impl(&globals_weak, &constants, &runtime, args)
+        }),
+    }));
+    globals
+        .borrow_mut()
+        .insert("thread_spawn".to_string(), thread_spawn);
+}
+
+// thread_spawn(f, args...) calls f on a new thread, in an interpreter of its own.
+// The arguments and the result are copied, nothing else is shared.
+fn thread_spawn_impl(
+    globals: &Weak<Globals>,
+    constants: &Constants,
+    runtime: &Rc<Runtime>,
+    args: &[LiteralValue],
+) -> Result<LiteralValue, String> {
+    let globals = globals
+        .upgrade()
+        .ok_or_else(|| "thread_spawn: the interpreter is gone".to_string())?;
+    let name = match &args[0] {
+        LiteralValue::Callable(CallableImpl::LoxFunction(fun))
+            if globals.borrow().get(&fun.name) == Some(&args[0]) =>
+        {
+            fun.name.clone()
+        }
+        other => {
+            return Err(format!(
+                "thread_spawn expected a function declared at the top level but got {}",
+                other.to_string()
+            ))
+        }
+    };
+    let arguments = args[1..]
+        .iter()
+        .map(SharedValue::copy)
+        .collect::<Result<Vec<_>, String>>()?;
+    let constants = copy_constants(&globals.borrow(), &constants.borrow())
+        .map_err(|e| format!("thread_spawn: {}", e))?;
+
+    let program = Program::from_sources(runtime.sources());
+    let config = runtime.config.clone();
+    let steps = runtime.step_counter();
+    let capture = runtime.is_capturing_output();
+    let thread_name = name.clone();
+    let handle = thread::Builder::new()
+        .stack_size(stack_size(&config))
+        .spawn(move || -> Finished {
+            let interpreter = match program.isolate(config, &constants) {
+                Ok(interpreter) => interpreter,
+                Err(e) => return (String::new(), Err(e), None),
+            };
+            interpreter.environment.runtime.share_step_counter(steps);
+            if capture {
+                interpreter.capture_output();
+            }
+            let result = call_global(&interpreter, &thread_name, &arguments);
+            (interpreter.take_output(), result, take_exit_status())
+        })
+        .map_err(|e| format!("thread_spawn: could not start a thread: {}", e))?;
+
+    Ok(thread_handle(name, handle, globals, runtime.clone()))
+}
+
+// An instance with join(), which waits for the thread and returns its result
+fn thread_handle(
+    name: String,
+    handle: JoinHandle<Finished>,
+    globals: Rc<Globals>,
+    runtime: Rc<Runtime>,
+) -> LiteralValue {
+    let handle = RefCell::new(Some(handle));
+    let thread_name = name.clone();
+    let join = LiteralValue::Callable(CallableImpl::NativeFunction(NativeFunctionImpl {
+        name: "join".to_string(),
+        arity: 0,
+        variadic: false,
+        fun: Rc::new(move |_args: &[LiteralValue]| {
+            let Some(handle) = handle.borrow_mut().take() else {
+                return Err(format!("Thread '{}' was already joined", thread_name));
+            };
+            let (output, result, exit_status) = handle
+                .join()
+                .map_err(|_| format!("Thread '{}' panicked", thread_name))?;
+
+            // A caller that collects its output, like the tests, gets the thread's too
+            for line in output.lines() {
+                runtime.print(line);
+            }
+            // A thread that called exit() exits the script that joins it
+            if let Some(code) = exit_status {
+                return Err(exit_error(code));
+            }
+            let result = result.map_err(|e| format!("Thread '{}' failed: {}", thread_name, e))?;
+            result.rebuild(&globals.borrow())
+        }),
+    }));
+
+    let value = record(
+        "Thread",
+        vec![
+            ("name".to_string(), LiteralValue::StringValue(name)),
+            ("join".to_string(), join),
+        ],
+    );
+    if let LiteralValue::LoxInstance { frozen, .. } = &value {
+        *frozen.borrow_mut() = true;
+    }
+    value
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::interpreter::Interpreter;
+    use crate::natives::process::take_exit_status;
+    use crate::run;
+    use crate::sandbox::{Capability, Config};
+
+    #[test]
+    fn exit_in_a_thread_exits_on_join() {
+        let mut interpreter = Interpreter::new();
+        interpreter.capture_output();
+        let source = "fun stop(code) { print \"stopping\"; exit(code); }
+            var thread = thread_spawn(stop, 3);
+            print \"spawned\";
+            thread.join();
+            print \"not reached\";";
+
+        assert_eq!(
+            run(&mut interpreter, source).unwrap_err(),
+            "Script exited with status 3"
+        );
+        assert_eq!(take_exit_status(), Some(3));
+        assert_eq!(interpreter.take_output(), "\"spawned\"\n\"stopping\"\n");
+    }
+
+    #[test]
+    fn threads_share_the_step_budget() {
+        let mut config = Config::sandboxed();
+        config.capabilities.insert(Capability::Threads);
+        config.max_steps = Some(2000);
+        let spin = "fun spin() { var i = 0; while (i < 1000) i = i + 1; return i; }";
+
+        let mut interpreter = Interpreter::with_config(config.clone());
+        let source = format!("{} thread_spawn(spin).join();", spin);
+        assert!(run(&mut interpreter, &source).is_ok());
+
+        // Each thread alone stays under the budget, together they do not
+        let mut interpreter = Interpreter::with_config(config);
+        let source = format!(
+            "{} var threads = [thread_spawn(spin), thread_spawn(spin), thread_spawn(spin)];
+            for (thread in threads) thread.join();",
+            spin
+        );
+        assert!(run(&mut interpreter, &source)
+            .unwrap_err()
+            .ends_with("Step budget of 2000 exceeded"));
+    }
+}
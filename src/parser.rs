@@ -1,11 +1,13 @@
-use crate::expr::{Expr, Expr::*, LiteralValue};
+use crate::expr::{Expr, Expr::*, LiteralValue, MatchArm, Parameter, Pattern};
 use crate::scanner::{Token, TokenType, TokenType::*};
-use crate::stmt::Stmt;
+use crate::stmt::{EnumVariant, Field, Stmt};
 
 pub struct Parser {
     tokens: Vec<Token>,
     current: usize,
     next_id: usize,
+    // Errors recovered from inside blocks and class bodies
+    errors: Vec<String>,
 }
 
 #[derive(Debug)]
@@ -20,6 +22,7 @@ impl Parser {
             tokens,
             current: 0,
             next_id: 0,
+            errors: vec![],
         }
     }
 
@@ -32,33 +35,46 @@ impl Parser {
 
     pub fn parse(&mut self) -> Result<Vec<Stmt>, String> {
         let mut stmts = vec![];
-        let mut errs = vec![];
 
         while !self.is_at_end() {
             let stmt = self.declaration();
             match stmt {
                 Ok(s) => stmts.push(s),
                 Err(msg) => {
-                    errs.push(msg);
+                    self.errors.push(msg);
                     self.synchronize();
+                    // A '}' without a block to close, skip it
+                    if self.check(RightBrace) {
+                        self.advance();
+                    }
                 }
             }
         }
 
-        if errs.len() == 0 {
+        if self.errors.is_empty() {
             Ok(stmts)
         } else {
-            Err(errs.join("\n"))
+            Err(std::mem::take(&mut self.errors).join("\n"))
         }
     }
 
     fn declaration(&mut self) -> Result<Stmt, String> {
         if self.match_token(Var) {
             self.var_declaration()
+        } else if self.match_token(Const) {
+            self.const_declaration()
         } else if self.match_token(Fun) {
             self.function(FunctionKind::Function)
+        } else if self.match_token(Async) {
+            self.async_function()
         } else if self.match_token(Class) {
             self.class_declaration()
+        } else if self.match_token(Extend) {
+            self.extend_declaration()
+        } else if self.match_token(Trait) {
+            self.trait_declaration()
+        } else if self.match_token(Enum) {
+            self.enum_declaration()
         } else {
             self.statement()
         }
@@ -76,23 +92,139 @@ impl Parser {
             None
         };
 
-        self.consume(LeftBrace, "Expected '{' before class body.")?;
-
-        let mut methods = vec![];
-        while !self.check(RightBrace) && !self.is_at_end() {
-            let method = self.function(FunctionKind::Method)?;
-            methods.push(Box::new(method));
+        let mut traits = vec![];
+        if self.match_token(With) {
+            loop {
+                self.consume(Identifier, "Expected trait name after 'with'.")?;
+                traits.push(Expr::Variable {
+                    id: self.get_id(),
+                    name: self.previous(),
+                });
+                if !self.match_token(Comma) {
+                    break;
+                }
+            }
         }
 
-        self.consume(RightBrace, "Expected '}' after class body.")?;
+        let (methods, fields) = self.class_body()?;
+        let methods = methods.into_iter().map(Box::new).collect();
 
         Ok(Stmt::Class {
             name,
+            fields,
             methods,
             superclass,
+            traits,
         })
     }
 
+    fn enum_declaration(&mut self) -> Result<Stmt, String> {
+        let name = self.consume(Identifier, "Expected name after 'enum' keyword.")?;
+        self.consume(LeftBrace, "Expected '{' before enum body.")?;
+
+        let mut variants = vec![];
+        while !self.check(RightBrace) && !self.is_at_end() {
+            let variant = self.consume(Identifier, "Expected variant name.")?;
+            let fields = if self.match_token(LeftParen) {
+                let mut fields = vec![];
+                while !self.check(RightParen) && !self.is_at_end() {
+                    fields.push(self.consume(Identifier, "Expected field name.")?);
+                    if !self.match_token(Comma) {
+                        break;
+                    }
+                }
+                self.consume(RightParen, "Expected ')' after variant fields.")?;
+                Some(fields)
+            } else {
+                None
+            };
+            variants.push(EnumVariant {
+                name: variant,
+                fields,
+            });
+
+            if !self.match_token(Comma) {
+                break;
+            }
+        }
+        self.consume(RightBrace, "Expected '}' after enum variants.")?;
+
+        Ok(Stmt::Enum { name, variants })
+    }
+
+    fn trait_declaration(&mut self) -> Result<Stmt, String> {
+        let name = self.consume(Identifier, "Expected name after 'trait' keyword.")?;
+        let methods = self.methods_body()?.into_iter().map(Box::new).collect();
+
+        Ok(Stmt::Trait { name, methods })
+    }
+
+    fn extend_declaration(&mut self) -> Result<Stmt, String> {
+        self.consume(Identifier, "Expected class name after 'extend' keyword.")?;
+        let class = Expr::Variable {
+            id: self.get_id(),
+            name: self.previous(),
+        };
+        let methods = self.methods_body()?.into_iter().map(Box::new).collect();
+
+        Ok(Stmt::Extend { class, methods })
+    }
+
+    // Methods and `name: Type;` field declarations
+    fn class_body(&mut self) -> Result<(Vec<Stmt>, Vec<Field>), String> {
+        self.consume(LeftBrace, "Expected '{' before class body.")?;
+
+        let mut methods = vec![];
+        let mut fields = vec![];
+        while !self.check(RightBrace) && !self.is_at_end() {
+            if self.check(Identifier) && self.check_next(Colon) {
+                let name = self.advance();
+                self.advance();
+                match self.field_annotation() {
+                    Ok(annotation) => fields.push(Field { name, annotation }),
+                    Err(msg) => {
+                        self.errors.push(msg);
+                        self.synchronize_class();
+                    }
+                }
+                continue;
+            }
+            match self.function(FunctionKind::Method) {
+                Ok(method) => methods.push(method),
+                Err(msg) => {
+                    self.errors.push(msg);
+                    self.synchronize_class();
+                }
+            }
+        }
+
+        self.consume(RightBrace, "Expected '}' after class body.")?;
+        Ok((methods, fields))
+    }
+
+    // Traits and extensions hold methods only
+    fn methods_body(&mut self) -> Result<Vec<Stmt>, String> {
+        let (methods, fields) = self.class_body()?;
+        match fields.first() {
+            Some(field) => Err(self.error_at(&field.name, "Only classes can declare fields")),
+            None => Ok(methods),
+        }
+    }
+
+    fn field_annotation(&mut self) -> Result<Token, String> {
+        let annotation = self.type_annotation()?;
+        self.consume(Semicolon, "Expected ';' after field declaration.")?;
+        Ok(annotation)
+    }
+
+    // The name of a type after ':' or '->', like `Number` or a class name
+    fn type_annotation(&mut self) -> Result<Token, String> {
+        if self.match_token(Nil) {
+            return Ok(self.previous());
+        }
+        self.consume(Identifier, "Expected type name")
+    }
+
     fn function(&mut self, kind: FunctionKind) -> Result<Stmt, String> {
         let name = self.consume(Identifier, &format!("Expected {kind:?} name"))?;
 
@@ -103,30 +235,26 @@ impl Parser {
             return Ok(Stmt::CmdFunction {
                 name,
                 cmd: cmd_body.lexeme,
+                is_async: false,
             });
         }
 
         self.consume(LeftParen, &format!("Expected '(' after {kind:?} name"))?;
+        let parameters = self.parameters()?;
+        self.consume(RightParen, "Expected ')' after parameters.")?;
 
-        let mut parameters = vec![];
-        if !self.check(RightParen) {
-            loop {
-                if parameters.len() >= 255 {
-                    let location = self.peek().line_number;
-                    return Err(format!(
-                        "Line {location}: Cant have more than 255 arguments"
-                    ));
-                }
-
-                let param = self.consume(Identifier, "Expected parameter name")?;
-                parameters.push(param);
-
-                if !self.match_token(Comma) {
-                    break;
-                }
-            }
+        if matches!(kind, FunctionKind::Method) && self.match_token(Semicolon) {
+            return Ok(Stmt::AbstractMethod {
+                name,
+                params: parameters,
+            });
         }
-        self.consume(RightParen, "Expected ')' after parameters.")?;
+
+        let return_type = if self.match_token(Arrow) {
+            Some(self.type_annotation()?)
+        } else {
+            None
+        };
 
         self.consume(LeftBrace, &format!("Expected '{{' before {kind:?} body."))?;
         let body = match self.block_statement()? {
@@ -137,12 +265,51 @@ impl Parser {
         Ok(Stmt::Function {
             name,
             params: parameters,
+            return_type,
             body,
+            is_async: false,
         })
     }
 
+    fn async_function(&mut self) -> Result<Stmt, String> {
+        self.consume(Fun, "Expected 'fun' after 'async'")?;
+        match self.function(FunctionKind::Function)? {
+            Stmt::Function {
+                name,
+                params,
+                return_type,
+                body,
+                is_async: _,
+            } => Ok(Stmt::Function {
+                name,
+                params,
+                return_type,
+                body,
+                is_async: true,
+            }),
+            Stmt::CmdFunction {
+                name,
+                cmd,
+                is_async: _,
+            } => Ok(Stmt::CmdFunction {
+                name,
+                cmd,
+                is_async: true,
+            }),
+            _ => panic!("Parsed something that was not a function after 'async'"),
+        }
+    }
+
     fn var_declaration(&mut self) -> Result<Stmt, String> {
+        if self.check(LeftParen) {
+            return self.destructure_declaration();
+        }
         let token = self.consume(Identifier, "Expected variable name")?;
+        let annotation = if self.match_token(Colon) {
+            Some(self.type_annotation()?)
+        } else {
+            None
+        };
 
         let initializer;
         if self.match_token(Equal) {
@@ -157,6 +324,34 @@ impl Parser {
         self.consume(Semicolon, "Expected ';' after variable declaration")?;
 
         Ok(Stmt::Var {
+            name: token,
+            annotation,
+            initializer,
+        })
+    }
+
+    fn destructure_declaration(&mut self) -> Result<Stmt, String> {
+        // var (q, r) = divmod(7, 2);
+        let keyword = self.previous();
+        let pattern = self.single_pattern()?;
+        self.consume(Equal, "Expected '=' after variable names")?;
+        let initializer = self.expression()?;
+        self.consume(Semicolon, "Expected ';' after variable declaration")?;
+
+        Ok(Stmt::Destructure {
+            keyword,
+            pattern,
+            initializer,
+        })
+    }
+
+    fn const_declaration(&mut self) -> Result<Stmt, String> {
+        let token = self.consume(Identifier, "Expected constant name")?;
+        self.consume(Equal, "Expected '=' after constant name, constants must be initialized")?;
+        let initializer = self.expression()?;
+        self.consume(Semicolon, "Expected ';' after constant declaration")?;
+
+        Ok(Stmt::Const {
             name: token,
             initializer,
         })
@@ -175,6 +370,8 @@ impl Parser {
             self.for_statement()
         } else if self.match_token(Return) {
             self.return_statement()
+        } else if self.match_token(Yield) {
+            self.yield_statement()
         } else {
             self.expression_statement()
         }
@@ -194,11 +391,29 @@ impl Parser {
         Ok(Stmt::ReturnStmt { keyword, value })
     }
 
+    fn yield_statement(&mut self) -> Result<Stmt, String> {
+        // yield; hands out nil
+        let keyword = self.previous();
+        let value = if self.check(Semicolon) {
+            None
+        } else {
+            Some(self.expression()?)
+        };
+        self.consume(Semicolon, "Expected ';' after yield value")?;
+
+        Ok(Stmt::Yield { keyword, value })
+    }
+
     fn for_statement(&mut self) -> Result<Stmt, String> {
         // for v
         //       ( SMTH ; SMTH ; SMTH )
         self.consume(LeftParen, "Expected '(' after 'for'.")?;
 
+        // for (x in SMTH)
+        if self.check(Identifier) && self.check_next(In) {
+            return self.for_in_statement();
+        }
+
         // Consumes "SMTH ;"
         let initializer;
         if self.match_token(Semicolon) {
@@ -265,6 +480,21 @@ impl Parser {
         Ok(body)
     }
 
+    fn for_in_statement(&mut self) -> Result<Stmt, String> {
+        let variable = self.consume(Identifier, "Expected loop variable name.")?;
+        self.consume(In, "Expected 'in' after loop variable.")?;
+        let iterable = self.expression()?;
+        self.consume(RightParen, "Expected ')' after for-in clause.")?;
+
+        let body = self.statement()?;
+
+        Ok(Stmt::ForIn {
+            variable,
+            iterable,
+            body: Box::new(body),
+        })
+    }
+
     fn while_statement(&mut self) -> Result<Stmt, String> {
         self.consume(LeftParen, "Expected '(' after 'while'")?;
         let condition = self.expression()?;
@@ -298,14 +528,26 @@ impl Parser {
     }
 
     fn block_statement(&mut self) -> Result<Stmt, String> {
+        let brace = self.previous();
         let mut statements = vec![];
 
         while !self.check(RightBrace) && !self.is_at_end() {
-            let decl = self.declaration()?;
-            statements.push(Box::new(decl));
+            match self.declaration() {
+                Ok(decl) => statements.push(Box::new(decl)),
+                Err(msg) => {
+                    self.errors.push(msg);
+                    self.synchronize();
+                }
+            }
         }
 
-        self.consume(RightBrace, "Expected '}' after a block")?;
+        self.consume(
+            RightBrace,
+            &format!(
+                "Expected '}}' to close the block opened at line {}, column {}",
+                brace.line_number, brace.column
+            ),
+        )?;
         Ok(Stmt::Block { statements })
     }
 
@@ -327,24 +569,7 @@ impl Parser {
 
     fn function_expression(&mut self) -> Result<Expr, String> {
         let paren = self.consume(LeftParen, "Expected '(' after anonymous function")?;
-        let mut parameters = vec![];
-        if !self.check(RightParen) {
-            loop {
-                if parameters.len() >= 255 {
-                    let location = self.peek().line_number;
-                    return Err(format!(
-                        "Line {location}: Cant have more than 255 arguments"
-                    ));
-                }
-
-                let param = self.consume(Identifier, "Expected parameter name")?;
-                parameters.push(param);
-
-                if !self.match_token(Comma) {
-                    break;
-                }
-            }
-        }
+        let parameters = self.parameters()?;
         self.consume(
             RightParen,
             "Expected ')' after anonymous function parameters",
@@ -368,11 +593,84 @@ impl Parser {
         })
     }
 
+    fn parameters(&mut self) -> Result<Vec<Parameter>, String> {
+        // a, b = 2, ...rest
+        let mut parameters: Vec<Parameter> = vec![];
+        if self.check(RightParen) {
+            return Ok(parameters);
+        }
+
+        loop {
+            let token = self.peek();
+            if parameters.len() >= 255 {
+                return Err(self.error_at(&token, "Cant have more than 255 parameters"));
+            }
+            if parameters.last().is_some_and(|p| p.is_rest) {
+                return Err(self.error_at(&token, "Rest parameter must be the last parameter"));
+            }
+
+            let is_rest = self.match_token(DotDotDot);
+            if !is_rest && self.check(LeftParen) {
+                // (a, b) destructures the argument, bound under a name no code can refer to
+                let pattern = self.single_pattern()?;
+                let name = Token {
+                    lexeme: format!("({})", parameters.len()),
+                    ..token
+                };
+                parameters.push(Parameter {
+                    pattern: Some(pattern),
+                    ..Parameter::new(name)
+                });
+                if !self.match_token(Comma) {
+                    break;
+                }
+                continue;
+            }
+            let name = self.consume(Identifier, "Expected parameter name")?;
+            let annotation = if !is_rest && self.match_token(Colon) {
+                Some(self.type_annotation()?)
+            } else {
+                None
+            };
+            let default = if self.match_token(Equal) {
+                if is_rest {
+                    return Err(self.error_at(&token, "Rest parameter cannot have a default value"));
+                }
+                Some(self.expression()?)
+            } else {
+                None
+            };
+
+            let follows_default = parameters.iter().any(|p| p.default.is_some());
+            if follows_default && default.is_none() && !is_rest {
+                return Err(self.error_at(
+                    &name,
+                    "Parameter without a default value cannot follow parameters with defaults",
+                ));
+            }
+
+            parameters.push(Parameter {
+                name,
+                default,
+                is_rest,
+                pattern: None,
+                annotation,
+            });
+
+            if !self.match_token(Comma) {
+                break;
+            }
+        }
+
+        Ok(parameters)
+    }
+
     fn assignment(&mut self) -> Result<Expr, String> {
         // a = 2; NOT var a = 2;
         let expr = self.pipe()?; // a |> f = 2;
 
         if self.match_token(Equal) {
+            let equals = self.previous();
             let value = self.expression()?;
 
             match expr {
@@ -391,7 +689,26 @@ impl Parser {
                     name,
                     value: Box::new(value),
                 }),
-                _ => Err("Invalid assignment target.".to_string()),
+                Index {
+                    id: _,
+                    object,
+                    bracket,
+                    index,
+                } => Ok(SetIndex {
+                    id: self.get_id(),
+                    object,
+                    bracket,
+                    index,
+                    value: Box::new(value),
+                }),
+                Tuple { id: _, elements } if elements.iter().all(is_tuple_target) => {
+                    Ok(AssignTuple {
+                        id: self.get_id(),
+                        targets: elements,
+                        value: Box::new(value),
+                    })
+                }
+                _ => Err(self.error_at(&equals, "Invalid assignment target")),
             }
         } else {
             Ok(expr)
@@ -407,16 +724,54 @@ impl Parser {
 
         // expr |> fun (a) { return a + 1; }
         // expr |> a -> a + 1
+        // expr |> f(_, 2) -> Call { f, arguments: [expr, 2] }
         let mut expr = self.or()?;
         while self.match_token(Pipe) {
             let pipe = self.previous();
             let function = self.or()?;
 
-            expr = Call {
-                id: self.get_id(),
-                callee: Box::new(function),
-                paren: pipe,
-                arguments: vec![expr],
+            expr = match function {
+                Call {
+                    id,
+                    callee,
+                    paren,
+                    arguments,
+                    named_arguments,
+                } if arguments.iter().any(is_placeholder) => {
+                    if arguments.iter().filter(|arg| is_placeholder(arg)).count() > 1 {
+                        return Err(self.error_at(
+                            &pipe,
+                            "Only one '_' placeholder is allowed in a piped call",
+                        ));
+                    }
+
+                    let mut piped = Some(expr);
+                    let arguments = arguments
+                        .into_iter()
+                        .map(|arg| {
+                            if is_placeholder(&arg) {
+                                piped.take().unwrap()
+                            } else {
+                                arg
+                            }
+                        })
+                        .collect();
+
+                    Call {
+                        id,
+                        callee,
+                        paren,
+                        arguments,
+                        named_arguments,
+                    }
+                }
+                function => Call {
+                    id: self.get_id(),
+                    callee: Box::new(function),
+                    paren: pipe,
+                    arguments: vec![expr],
+                    named_arguments: vec![],
+                },
             };
         }
         Ok(expr)
@@ -459,7 +814,7 @@ impl Parser {
 
     fn equality(&mut self) -> Result<Expr, String> {
         let mut expr = self.comparison()?;
-        while self.match_tokens(&[BangEqual, EqualEqual]) {
+        while self.match_tokens(&[BangEqual, EqualEqual, Is]) {
             let operator = self.previous();
             let rhs = self.comparison()?;
             expr = Binary {
@@ -474,9 +829,27 @@ impl Parser {
     }
 
     fn comparison(&mut self) -> Result<Expr, String> {
-        let mut expr = self.term()?;
+        let mut expr = self.range()?;
 
         while self.match_tokens(&[Greater, GreaterEqual, Less, LessEqual]) {
+            let op = self.previous();
+            let rhs = self.range()?;
+            expr = Binary {
+                id: self.get_id(),
+                left: Box::from(expr),
+                operator: op,
+                right: Box::from(rhs),
+            };
+        }
+
+        Ok(expr)
+    }
+
+    fn range(&mut self) -> Result<Expr, String> {
+        // 0..10 and 0..=10, ranges do not chain
+        let mut expr = self.term()?;
+
+        if self.match_tokens(&[DotDot, DotDotEqual]) {
             let op = self.previous();
             let rhs = self.term()?;
             expr = Binary {
@@ -524,7 +897,15 @@ impl Parser {
     }
 
     fn unary(&mut self) -> Result<Expr, String> {
-        if self.match_tokens(&[Bang, Minus]) {
+        if self.match_token(Await) {
+            let keyword = self.previous();
+            let value = self.unary()?;
+            Ok(Expr::Await {
+                id: self.get_id(),
+                keyword,
+                value: Box::new(value),
+            })
+        } else if self.match_tokens(&[Bang, Minus]) {
             let op = self.previous();
             let rhs = self.unary()?;
             Ok(Unary {
@@ -543,6 +924,16 @@ impl Parser {
         loop {
             if self.match_token(LeftParen) {
                 expr = self.finish_call(expr)?;
+            } else if self.match_token(LeftBracket) {
+                let bracket = self.previous();
+                let index = self.expression()?;
+                self.consume(RightBracket, "Expected ']' after index.")?;
+                expr = Index {
+                    id: self.get_id(),
+                    object: Box::new(expr),
+                    bracket,
+                    index: Box::new(index),
+                };
             } else if self.match_token(Dot) {
                 let name = self.consume(Identifier, "Expected token after dot-accessor")?;
                 expr = Get {
@@ -560,16 +951,28 @@ impl Parser {
 
     fn finish_call(&mut self, callee: Expr) -> Result<Expr, String> {
         let mut arguments = vec![];
+        let mut named_arguments = vec![];
 
         if !self.check(RightParen) {
             loop {
-                let arg = self.expression()?;
-                arguments.push(arg);
-                if arguments.len() >= 255 {
-                    let location = self.peek().line_number;
-                    return Err(format!(
-                        "Line {location}: Cant have more than 255 arguments"
+                if self.check(Identifier) && self.check_next(Colon) {
+                    // f(b: 3)
+                    let name = self.advance();
+                    self.advance();
+                    named_arguments.push((name, self.expression()?));
+                } else if !named_arguments.is_empty() {
+                    let token = self.peek();
+                    return Err(self.error_at(
+                        &token,
+                        "Positional arguments cannot follow named arguments",
                     ));
+                } else {
+                    let arg = self.expression()?;
+                    arguments.push(arg);
+                }
+                if arguments.len() + named_arguments.len() >= 255 {
+                    let token = self.peek();
+                    return Err(self.error_at(&token, "Cant have more than 255 arguments"));
                 }
 
                 if !self.match_token(Comma) {
@@ -584,6 +987,7 @@ impl Parser {
             callee: Box::new(callee),
             paren,
             arguments,
+            named_arguments,
         })
     }
 
@@ -591,13 +995,66 @@ impl Parser {
         let token = self.peek();
         let result;
         match token.token_type {
+            LeftParen if self.is_lambda_start() => {
+                self.advance();
+                let mut parameters = vec![];
+                if !self.check(RightParen) {
+                    loop {
+                        let param = self.consume(Identifier, "Expected parameter name")?;
+                        parameters.push(param);
+
+                        if !self.match_token(Comma) {
+                            break;
+                        }
+                    }
+                }
+                self.consume(RightParen, "Expected ')' after lambda parameters")?;
+                result = self.lambda(parameters)?;
+            }
             LeftParen => {
                 self.advance();
+                if self.match_token(RightParen) {
+                    return Ok(Expr::Tuple {
+                        id: self.get_id(),
+                        elements: vec![],
+                    });
+                }
                 let expr = self.expression()?;
-                self.consume(RightParen, "Expected ')'")?;
-                result = Grouping {
+                if self.match_token(Comma) {
+                    // (a, b) and (a,)
+                    let mut elements = vec![expr];
+                    while !self.check(RightParen) && !self.is_at_end() {
+                        elements.push(self.expression()?);
+                        if !self.match_token(Comma) {
+                            break;
+                        }
+                    }
+                    self.consume(RightParen, "Expected ')' after tuple elements")?;
+                    result = Expr::Tuple {
+                        id: self.get_id(),
+                        elements,
+                    };
+                } else {
+                    self.consume(RightParen, "Expected ')'")?;
+                    result = Grouping {
+                        id: self.get_id(),
+                        expression: Box::from(expr),
+                    };
+                }
+            }
+            LeftBracket => {
+                self.advance();
+                let mut elements = vec![];
+                while !self.check(RightBracket) && !self.is_at_end() {
+                    elements.push(self.expression()?);
+                    if !self.match_token(Comma) {
+                        break;
+                    }
+                }
+                self.consume(RightBracket, "Expected ']' after list elements")?;
+                result = Expr::List {
                     id: self.get_id(),
-                    expression: Box::from(expr),
+                    elements,
                 };
             }
             False | True | Nil | Number | StringLit => {
@@ -607,6 +1064,10 @@ impl Parser {
                     value: LiteralValue::from_token(token),
                 }
             }
+            Identifier if self.check_next(Arrow) => {
+                self.advance();
+                result = self.lambda(vec![token])?;
+            }
             Identifier => {
                 self.advance();
                 result = Variable {
@@ -637,12 +1098,200 @@ impl Parser {
                 self.advance();
                 result = self.function_expression()?;
             }
-            _ => return Err("Expected expression".to_string()),
+            TokenType::Match => {
+                self.advance();
+                result = self.match_expression(token)?;
+            }
+            _ => return Err(self.error_at(&token, "Expected expression")),
         }
 
         Ok(result)
     }
 
+    fn match_expression(&mut self, keyword: Token) -> Result<Expr, String> {
+        // match value { 1 => a, "a" | "b" => b, n if n > 10 => c, _ => d }
+        let subject = self.expression()?;
+        self.consume(LeftBrace, "Expected '{' after match subject.")?;
+
+        let mut arms = vec![];
+        while !self.check(RightBrace) && !self.is_at_end() {
+            let pattern = self.pattern()?;
+            let guard = if self.match_token(If) {
+                Some(self.expression()?)
+            } else {
+                None
+            };
+            let arrow = self.consume(FatArrow, "Expected '=>' after match pattern.")?;
+
+            // A block arm runs like the body of an anonymous function called in place,
+            // so 'return' inside of it gives the value of the arm
+            let body = if self.match_token(LeftBrace) {
+                let body = match self.block_statement()? {
+                    Stmt::Block { statements } => statements,
+                    _ => panic!("Block statement parsed something that was not a block"),
+                };
+                let function = Expr::AnonFunction {
+                    id: self.get_id(),
+                    paren: arrow.clone(),
+                    arguments: vec![],
+                    body,
+                };
+                Call {
+                    id: self.get_id(),
+                    callee: Box::new(function),
+                    paren: arrow,
+                    arguments: vec![],
+                    named_arguments: vec![],
+                }
+            } else {
+                self.expression()?
+            };
+
+            arms.push(MatchArm {
+                pattern,
+                guard,
+                body,
+            });
+
+            if !self.match_token(Comma) {
+                break;
+            }
+        }
+        self.consume(RightBrace, "Expected '}' after match arms.")?;
+
+        Ok(Expr::Match {
+            id: self.get_id(),
+            keyword,
+            subject: Box::new(subject),
+            arms,
+        })
+    }
+
+    fn pattern(&mut self) -> Result<Pattern, String> {
+        let pattern = self.single_pattern()?;
+        if !self.check(Bar) {
+            return Ok(pattern);
+        }
+
+        let mut alternatives = vec![pattern];
+        while self.match_token(Bar) {
+            alternatives.push(self.single_pattern()?);
+        }
+
+        Ok(Pattern::Alternatives(alternatives))
+    }
+
+    fn single_pattern(&mut self) -> Result<Pattern, String> {
+        let token = self.peek();
+        match token.token_type {
+            False | True | Nil | Number | StringLit => {
+                self.advance();
+                Ok(Pattern::Literal(LiteralValue::from_token(token)))
+            }
+            Minus => {
+                self.advance();
+                let number = self.consume(Number, "Expected number after '-' in pattern.")?;
+                match LiteralValue::from_token(number) {
+                    LiteralValue::Number(x) => Ok(Pattern::Literal(LiteralValue::Number(-x))),
+                    _ => panic!("Number token did not hold a number"),
+                }
+            }
+            LeftParen => {
+                // (q, r)
+                self.advance();
+                let mut patterns = vec![];
+                while !self.check(RightParen) && !self.is_at_end() {
+                    patterns.push(self.pattern()?);
+                    if !self.match_token(Comma) {
+                        break;
+                    }
+                }
+                self.consume(RightParen, "Expected ')' after tuple pattern.")?;
+
+                Ok(Pattern::Tuple(patterns))
+            }
+            Identifier if token.lexeme == "_" => {
+                self.advance();
+                Ok(Pattern::Wildcard)
+            }
+            Identifier if self.check_next(LeftBrace) => {
+                // Point { x, y: 0 }
+                self.advance();
+                self.advance();
+                let mut fields = vec![];
+                while !self.check(RightBrace) && !self.is_at_end() {
+                    let field = self.consume(Identifier, "Expected field name in pattern.")?;
+                    let pattern = if self.match_token(Colon) {
+                        self.pattern()?
+                    } else {
+                        Pattern::Binding(field.clone())
+                    };
+                    fields.push((field, pattern));
+
+                    if !self.match_token(Comma) {
+                        break;
+                    }
+                }
+                self.consume(RightBrace, "Expected '}' after fields in pattern.")?;
+
+                Ok(Pattern::Instance {
+                    class: token,
+                    fields,
+                })
+            }
+            Identifier => {
+                self.advance();
+                Ok(Pattern::Binding(token))
+            }
+            _ => Err(self.error_at(&token, "Expected pattern")),
+        }
+    }
+
+    fn lambda(&mut self, parameters: Vec<Token>) -> Result<Expr, String> {
+        // x -> x + 1
+        // (a, b) -> { return a * b; }
+        let arrow = self.consume(Arrow, "Expected '->' after lambda parameters")?;
+
+        let body = if self.match_token(LeftBrace) {
+            match self.block_statement()? {
+                Stmt::Block { statements } => statements,
+                _ => panic!("Block statement parsed something that was not a block"),
+            }
+        } else {
+            let value = self.expression()?;
+            vec![Box::new(Stmt::ReturnStmt {
+                keyword: arrow.clone(),
+                value: Some(value),
+            })]
+        };
+
+        Ok(Expr::AnonFunction {
+            id: self.get_id(),
+            paren: arrow,
+            arguments: parameters.into_iter().map(Parameter::new).collect(),
+            body,
+        })
+    }
+
+    fn is_lambda_start(&mut self) -> bool {
+        // Looks past a '(' for "() ->" or "(a, b) ->" without consuming anything
+        let mut idx = self.current + 1;
+        if self.tokens[idx].token_type != RightParen {
+            loop {
+                if self.tokens[idx].token_type != Identifier {
+                    return false;
+                }
+                idx += 1;
+                if self.tokens[idx].token_type != Comma {
+                    break;
+                }
+                idx += 1;
+            }
+        }
+
+        self.tokens[idx].token_type == RightParen && self.tokens[idx + 1].token_type == Arrow
+    }
+
     fn consume(&mut self, token_type: TokenType, msg: &str) -> Result<Token, String> {
         let token = self.peek();
         if token.token_type == token_type {
@@ -650,14 +1299,35 @@ impl Parser {
             let token = self.previous();
             Ok(token)
         } else {
-            Err(format!("Line {}: {}", token.line_number, msg))
+            Err(self.error_at(&token, msg))
         }
     }
 
+    // Every parse error says where it happened and which token it happened at
+    fn error_at(&self, token: &Token, msg: &str) -> String {
+        let location = if token.token_type == Eof {
+            "at end".to_string()
+        } else {
+            format!("at '{}'", token.lexeme)
+        };
+
+        format!(
+            "Line {}, column {} {}: {}",
+            token.line_number, token.column, location, msg
+        )
+    }
+
     fn check(&mut self, typ: TokenType) -> bool {
         self.peek().token_type == typ
     }
 
+    fn check_next(&mut self, typ: TokenType) -> bool {
+        match self.tokens.get(self.current + 1) {
+            Some(token) => token.token_type == typ,
+            None => false,
+        }
+    }
+
     fn match_token(&mut self, typ: TokenType) -> bool {
         if self.is_at_end() {
             false
@@ -702,6 +1372,10 @@ impl Parser {
     }
 
     fn synchronize(&mut self) {
+        // A '}' ends the block we are in, leave it for the block to consume
+        if self.check(RightBrace) {
+            return;
+        }
         self.advance();
 
         while !self.is_at_end() {
@@ -710,7 +1384,35 @@ impl Parser {
             }
 
             match self.peek().token_type {
-                Class | Fun | Var | For | If | While | Print | Return => return,
+                Class | Enum | Extend | Trait | Fun | Async | Var | Const | For | If | While
+                | Print | Return | Yield | RightBrace => return,
+                _ => (),
+            }
+
+            self.advance();
+        }
+    }
+
+    // Skips to the next method or the end of the class body, stepping over the
+    // braces of a broken method so they do not end the class early
+    fn synchronize_class(&mut self) {
+        let start = self.current;
+        let mut depth = 0;
+
+        while !self.is_at_end() {
+            match self.peek().token_type {
+                LeftBrace => depth += 1,
+                RightBrace if depth == 0 => return,
+                RightBrace => {
+                    depth -= 1;
+                    if depth == 0 {
+                        self.advance();
+                        return;
+                    }
+                }
+                Identifier if depth == 0 && self.check_next(LeftParen) && self.current > start => {
+                    return
+                }
                 _ => (),
             }
 
@@ -719,6 +1421,19 @@ impl Parser {
     }
 }
 
+// Variables and nested tuples of variables can be assigned to together
+fn is_tuple_target(expr: &Expr) -> bool {
+    match expr {
+        Variable { .. } => true,
+        Expr::Tuple { id: _, elements } => elements.iter().all(is_tuple_target),
+        _ => false,
+    }
+}
+
+fn is_placeholder(expr: &Expr) -> bool {
+    matches!(expr, Variable { id: _, name } if name.lexeme == "_")
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -731,30 +1446,35 @@ mod tests {
             lexeme: "1".to_string(),
             literal: Some(FValue(1.0)),
             line_number: 0,
+            column: 0,
         };
         let plus = Token {
             token_type: Plus,
             lexeme: "+".to_string(),
             literal: None,
             line_number: 0,
+            column: 0,
         };
         let two = Token {
             token_type: Number,
             lexeme: "2".to_string(),
             literal: Some(FValue(2.0)),
             line_number: 0,
+            column: 0,
         };
         let semicol = Token {
             token_type: Semicolon,
             lexeme: ";".to_string(),
             literal: None,
             line_number: 0,
+            column: 0,
         };
         let eof = Token {
             token_type: Eof,
             lexeme: "".to_string(),
             literal: None,
             line_number: 0,
+            column: 0,
         };
 
         let tokens = vec![one, plus, two, semicol, eof];
@@ -778,6 +1498,18 @@ mod tests {
         assert_eq!(string_expr, "(== (+ 1 2) (+ 5 7))");
     }
 
+    #[test]
+    fn test_range_precedence() {
+        let source = "1 + 1..n * 2 < 3;";
+        let mut scanner = Scanner::new(source);
+        let tokens = scanner.scan_tokens().unwrap();
+        let mut parser = Parser::new(tokens);
+        let parsed_expr = parser.parse().unwrap();
+        let string_expr = parsed_expr[0].to_string();
+
+        assert_eq!(string_expr, "(< (.. (+ 1 1) (* (var n) 2)) 3)");
+    }
+
     #[test]
     fn test_eq_with_paren() {
         let source = "1 == (2 + 2);";
@@ -789,4 +1521,47 @@ mod tests {
 
         assert_eq!(string_expr, "(== 1 (group (+ 2 2)))");
     }
+
+    fn parse_errors(source: &str) -> Vec<String> {
+        let tokens = Scanner::new(source).scan_tokens().unwrap();
+        match Parser::new(tokens).parse() {
+            Ok(_) => vec![],
+            Err(msg) => msg.lines().map(String::from).collect(),
+        }
+    }
+
+    #[test]
+    fn errors_have_position_and_token() {
+        assert_eq!(
+            parse_errors("var a = 1;\nprint a +;"),
+            vec!["Line 2, column 10 at ';': Expected expression"]
+        );
+        assert_eq!(
+            parse_errors("print 1"),
+            vec!["Line 1, column 8 at end: Expected ';' after value."]
+        );
+    }
+
+    #[test]
+    fn recovers_inside_blocks_and_classes() {
+        let source = "fun f() {\n  var x = ;\n  print x\n}\nclass A {\n  m( { }\n  n() { return 1; }\n}\nprint f(;";
+        assert_eq!(
+            parse_errors(source),
+            vec![
+                "Line 2, column 11 at ';': Expected expression",
+                "Line 4, column 1 at '}': Expected ';' after value.",
+                "Line 6, column 6 at '{': Expected parameter name",
+                "Line 9, column 9 at ';': Expected expression",
+            ]
+        );
+    }
+
+    #[test]
+    fn missing_brace_is_one_error() {
+        let source = "fun f(a) {\n  if (a) {\n    print a;\n  return a;\n}\nvar b = 1;\nprint b;";
+        assert_eq!(
+            parse_errors(source),
+            vec!["Line 7, column 9 at end: Expected '}' to close the block opened at line 1, column 10"]
+        );
+    }
 }
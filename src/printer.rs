@@ -0,0 +1,463 @@
+use crate::expr::{Expr, MatchArm, Parameter, Pattern};
+use crate::scanner::Token;
+use crate::stmt::Stmt;
+use std::collections::HashMap;
+
+// One token per line: line:column, type, lexeme and literal if there is one
+pub fn dump_tokens(tokens: &[Token]) -> String {
+    let mut out = String::new();
+    for token in tokens {
+        let token_type = token.token_type.to_string();
+        let position = format!("{}:{}", token.line_number, token.column);
+        let line = format!("{:>6} {:<12} {}", position, token_type, token.lexeme);
+        match &token.literal {
+            Some(literal) => out.push_str(&format!("{} {:?}", line, literal)),
+            None => out.push_str(line.trim_end()),
+        }
+        out.push('\n');
+    }
+
+    out
+}
+
+// The full tree, one node per line. Expressions show their id and, when the
+// resolver found them in a local scope, how many scopes up they resolve to.
+pub fn dump_ast(stmts: &[Stmt], locals: &HashMap<usize, usize>) -> String {
+    let mut printer = AstPrinter {
+        locals,
+        out: String::new(),
+        depth: 0,
+    };
+    for stmt in stmts {
+        printer.stmt(stmt);
+    }
+
+    printer.out
+}
+
+struct AstPrinter<'a> {
+    locals: &'a HashMap<usize, usize>,
+    out: String,
+    depth: usize,
+}
+
+impl AstPrinter<'_> {
+    fn line(&mut self, text: &str) {
+        for _ in 0..self.depth {
+            self.out.push_str("  ");
+        }
+        self.out.push_str(text);
+        self.out.push('\n');
+    }
+
+    // Prints a node and then its children one level deeper
+    fn node(&mut self, text: &str, children: impl FnOnce(&mut Self)) {
+        self.line(text);
+        self.depth += 1;
+        children(self);
+        self.depth -= 1;
+    }
+
+    fn stmts(&mut self, stmts: &[Box<Stmt>]) {
+        for stmt in stmts {
+            self.stmt(stmt);
+        }
+    }
+
+    fn stmt(&mut self, stmt: &Stmt) {
+        match stmt {
+            Stmt::Expression { expression } => self.node("Expression", |p| p.expr(expression)),
+            Stmt::Print { expression } => self.node("Print", |p| p.expr(expression)),
+            Stmt::Var {
+                name,
+                annotation,
+                initializer,
+            } => self.node(
+                &format!("Var {}{}", name.lexeme, annotation_suffix(annotation)),
+                |p| p.expr(initializer),
+            ),
+            Stmt::Const { name, initializer } => {
+                self.node(&format!("Const {}", name.lexeme), |p| p.expr(initializer))
+            }
+            Stmt::Destructure {
+                keyword: _,
+                pattern: target,
+                initializer,
+            } => self.node(&format!("Var {}", pattern(target)), |p| p.expr(initializer)),
+            Stmt::Block { statements } => self.node("Block", |p| p.stmts(statements)),
+            Stmt::Class {
+                name,
+                fields,
+                methods,
+                superclass,
+                traits,
+            } => self.node(&format!("Class {}", name.lexeme), |p| {
+                if let Some(superclass) = superclass {
+                    p.node("Superclass", |p| p.expr(superclass));
+                }
+                for trait_ in traits {
+                    p.node("With", |p| p.expr(trait_));
+                }
+                for field in fields {
+                    p.line(&format!(
+                        "Field {}: {}",
+                        field.name.lexeme, field.annotation.lexeme
+                    ));
+                }
+                p.stmts(methods);
+            }),
+            Stmt::Enum { name, variants } => self.node(&format!("Enum {}", name.lexeme), |p| {
+                for variant in variants {
+                    match &variant.fields {
+                        Some(fields) => {
+                            let fields = fields
+                                .iter()
+                                .map(|field| field.lexeme.clone())
+                                .collect::<Vec<String>>()
+                                .join(", ");
+                            p.line(&format!("Variant {}({})", variant.name.lexeme, fields))
+                        }
+                        None => p.line(&format!("Variant {}", variant.name.lexeme)),
+                    }
+                }
+            }),
+            Stmt::Trait { name, methods } => {
+                self.node(&format!("Trait {}", name.lexeme), |p| p.stmts(methods))
+            }
+            Stmt::AbstractMethod { name, params } => self.line(&format!(
+                "AbstractMethod {}({})",
+                name.lexeme,
+                parameter_list(params)
+            )),
+            Stmt::Extend { class, methods } => self.node("Extend", |p| {
+                p.expr(class);
+                p.stmts(methods);
+            }),
+            Stmt::IfStmt {
+                predicate,
+                then,
+                els,
+            } => self.node("If", |p| {
+                p.expr(predicate);
+                p.node("Then", |p| p.stmt(then));
+                if let Some(els) = els {
+                    p.node("Else", |p| p.stmt(els));
+                }
+            }),
+            Stmt::WhileStmt { condition, body } => self.node("While", |p| {
+                p.expr(condition);
+                p.stmt(body);
+            }),
+            Stmt::ForIn {
+                variable,
+                iterable,
+                body,
+            } => self.node(&format!("ForIn {}", variable.lexeme), |p| {
+                p.expr(iterable);
+                p.stmt(body);
+            }),
+            Stmt::Function {
+                name,
+                params,
+                return_type,
+                body,
+                is_async,
+            } => self.node(
+                &format!(
+                    "{}Function {}({}){}",
+                    if *is_async { "Async " } else { "" },
+                    name.lexeme,
+                    parameter_list(params),
+                    return_type
+                        .as_ref()
+                        .map(|t| format!(" -> {}", t.lexeme))
+                        .unwrap_or_default()
+                ),
+                |p| {
+                    p.defaults(params);
+                    p.stmts(body);
+                },
+            ),
+            Stmt::CmdFunction {
+                name,
+                cmd,
+                is_async,
+            } => self.line(&format!(
+                "{}CmdFunction {} <- {:?}",
+                if *is_async { "Async " } else { "" },
+                name.lexeme,
+                cmd
+            )),
+            Stmt::ReturnStmt { keyword: _, value } => self.node("Return", |p| {
+                if let Some(value) = value {
+                    p.expr(value);
+                }
+            }),
+            Stmt::Yield { keyword: _, value } => self.node("Yield", |p| {
+                if let Some(value) = value {
+                    p.expr(value);
+                }
+            }),
+        }
+    }
+
+    fn defaults(&mut self, params: &[Parameter]) {
+        for param in params {
+            if let Some(default) = &param.default {
+                self.node(&format!("Default {}", param.name.lexeme), |p| p.expr(default));
+            }
+        }
+    }
+
+    // `#id`, followed by the resolved scope distance for variable-like nodes
+    fn tag(&self, id: usize, resolvable: bool) -> String {
+        match self.locals.get(&id) {
+            Some(distance) => format!("#{} distance={}", id, distance),
+            None if resolvable => format!("#{} global", id),
+            None => format!("#{}", id),
+        }
+    }
+
+    fn expr(&mut self, expr: &Expr) {
+        match expr {
+            Expr::AnonFunction {
+                id,
+                paren: _,
+                arguments,
+                body,
+            } => self.node(
+                &format!(
+                    "AnonFunction({}) {}",
+                    parameter_list(arguments),
+                    self.tag(*id, false)
+                ),
+                |p| {
+                    p.defaults(arguments);
+                    p.stmts(body);
+                },
+            ),
+            Expr::Assign { id, name, value } => self.node(
+                &format!("Assign {} {}", name.lexeme, self.tag(*id, true)),
+                |p| p.expr(value),
+            ),
+            Expr::AssignTuple { id, targets, value } => {
+                self.node(&format!("AssignTuple {}", self.tag(*id, false)), |p| {
+                    p.node("Targets", |p| {
+                        for target in targets {
+                            p.expr(target);
+                        }
+                    });
+                    p.expr(value);
+                })
+            }
+            Expr::Await {
+                id,
+                keyword: _,
+                value,
+            } => self.node(&format!("Await {}", self.tag(*id, false)), |p| {
+                p.expr(value)
+            }),
+            Expr::Binary {
+                id,
+                left,
+                operator,
+                right,
+            } => self.node(
+                &format!("Binary {} {}", operator.lexeme, self.tag(*id, false)),
+                |p| {
+                    p.expr(left);
+                    p.expr(right);
+                },
+            ),
+            Expr::Call {
+                id,
+                callee,
+                paren: _,
+                arguments,
+                named_arguments,
+            } => self.node(&format!("Call {}", self.tag(*id, false)), |p| {
+                p.expr(callee);
+                for argument in arguments {
+                    p.expr(argument);
+                }
+                for (name, argument) in named_arguments {
+                    p.node(&format!("Named {}", name.lexeme), |p| p.expr(argument));
+                }
+            }),
+            Expr::Get { id, object, name } => self.node(
+                &format!("Get .{} {}", name.lexeme, self.tag(*id, false)),
+                |p| p.expr(object),
+            ),
+            Expr::Grouping { id, expression } => {
+                self.node(&format!("Grouping {}", self.tag(*id, false)), |p| {
+                    p.expr(expression)
+                })
+            }
+            Expr::Index {
+                id,
+                object,
+                bracket: _,
+                index,
+            } => self.node(&format!("Index {}", self.tag(*id, false)), |p| {
+                p.expr(object);
+                p.expr(index);
+            }),
+            Expr::List { id, elements } => {
+                self.node(&format!("List {}", self.tag(*id, false)), |p| {
+                    for element in elements {
+                        p.expr(element);
+                    }
+                })
+            }
+            Expr::Literal { id, value } => self.line(&format!(
+                "Literal {} {}",
+                value.to_string(),
+                self.tag(*id, false)
+            )),
+            Expr::Logical {
+                id,
+                left,
+                operator,
+                right,
+            } => self.node(
+                &format!("Logical {} {}", operator.lexeme, self.tag(*id, false)),
+                |p| {
+                    p.expr(left);
+                    p.expr(right);
+                },
+            ),
+            Expr::Match {
+                id,
+                keyword: _,
+                subject,
+                arms,
+            } => self.node(&format!("Match {}", self.tag(*id, false)), |p| {
+                p.expr(subject);
+                for arm in arms {
+                    p.arm(arm);
+                }
+            }),
+            Expr::Set {
+                id,
+                object,
+                name,
+                value,
+            } => self.node(
+                &format!("Set .{} {}", name.lexeme, self.tag(*id, false)),
+                |p| {
+                    p.expr(object);
+                    p.expr(value);
+                },
+            ),
+            Expr::SetIndex {
+                id,
+                object,
+                bracket: _,
+                index,
+                value,
+            } => self.node(&format!("SetIndex {}", self.tag(*id, false)), |p| {
+                p.expr(object);
+                p.expr(index);
+                p.expr(value);
+            }),
+            Expr::This { id, keyword: _ } => {
+                self.line(&format!("This {}", self.tag(*id, true)))
+            }
+            Expr::Super {
+                id,
+                keyword: _,
+                method,
+            } => self.line(&format!("Super .{} {}", method.lexeme, self.tag(*id, true))),
+            Expr::Tuple { id, elements } => {
+                self.node(&format!("Tuple {}", self.tag(*id, false)), |p| {
+                    for element in elements {
+                        p.expr(element);
+                    }
+                })
+            }
+            Expr::Unary {
+                id,
+                operator,
+                right,
+            } => self.node(
+                &format!("Unary {} {}", operator.lexeme, self.tag(*id, false)),
+                |p| p.expr(right),
+            ),
+            Expr::Variable { id, name } => {
+                self.line(&format!("Variable {} {}", name.lexeme, self.tag(*id, true)))
+            }
+        }
+    }
+
+    fn arm(&mut self, arm: &MatchArm) {
+        self.node(&format!("Arm {}", pattern(&arm.pattern)), |p| {
+            if let Some(guard) = &arm.guard {
+                p.node("Guard", |p| p.expr(guard));
+            }
+            p.expr(&arm.body);
+        });
+    }
+}
+
+fn parameter_list(params: &[Parameter]) -> String {
+    params
+        .iter()
+        .map(|param| {
+            if let Some(tuple) = &param.pattern {
+                pattern(tuple)
+            } else if param.is_rest {
+                format!("...{}", param.name.lexeme)
+            } else {
+                let name = param.name.lexeme.clone() + &annotation_suffix(&param.annotation);
+                if param.default.is_some() {
+                    format!("{name} = ?")
+                } else {
+                    name
+                }
+            }
+        })
+        .collect::<Vec<String>>()
+        .join(", ")
+}
+
+// `: Number` after a name, or nothing when it is not annotated
+fn annotation_suffix(annotation: &Option<Token>) -> String {
+    match annotation {
+        Some(annotation) => format!(": {}", annotation.lexeme),
+        None => String::new(),
+    }
+}
+
+fn pattern(pattern: &Pattern) -> String {
+    match pattern {
+        Pattern::Wildcard => "_".to_string(),
+        Pattern::Literal(value) => value.to_string(),
+        Pattern::Binding(name) => name.lexeme.clone(),
+        Pattern::Alternatives(alternatives) => alternatives
+            .iter()
+            .map(self::pattern)
+            .collect::<Vec<String>>()
+            .join(" | "),
+        Pattern::Instance { class, fields } => {
+            let fields = fields
+                .iter()
+                .map(|(name, field)| match field {
+                    Pattern::Binding(binding) if binding.lexeme == name.lexeme => {
+                        name.lexeme.clone()
+                    }
+                    _ => format!("{}: {}", name.lexeme, self::pattern(field)),
+                })
+                .collect::<Vec<String>>()
+                .join(", ");
+            format!("{} {{ {} }}", class.lexeme, fields)
+        }
+        Pattern::Tuple(patterns) => format!(
+            "({})",
+            patterns
+                .iter()
+                .map(self::pattern)
+                .collect::<Vec<String>>()
+                .join(", ")
+        ),
+    }
+}
@@ -1,43 +1,92 @@
-use crate::expr::Expr;
+use crate::expr::{Expr, Parameter, Pattern};
+use crate::generator::leading_await;
 use crate::scanner::Token;
 use crate::stmt::Stmt;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 
 #[derive(Copy, Clone, PartialEq)]
 enum FunctionType {
     None,
     Function,
+    AsyncFunction,
     Method,
+    Initializer,
 }
 
 #[allow(dead_code)]
 pub struct Resolver {
     scopes: Vec<HashMap<String, bool>>,
+    // Names in each scope that can not be assigned to, parallel to scopes
+    constants: Vec<HashSet<String>>,
     current_function: FunctionType,
+    // The await the statement being resolved starts with, if any
+    leading_await: Option<usize>,
     locals: HashMap<usize, usize>,
+    warnings: Vec<String>,
+}
+
+impl Default for Resolver {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Resolver {
     pub fn new() -> Self {
         Self {
             scopes: vec![],
+            constants: vec![],
             current_function: FunctionType::None,
+            leading_await: None,
             locals: HashMap::new(),
+            warnings: vec![],
         }
     }
 
+    pub fn warnings(&self) -> &[String] {
+        &self.warnings
+    }
+
     fn resolve_internal(&mut self, stmt: &Stmt) -> Result<(), String> {
+        self.leading_await = leading_await(stmt).map(|await_| await_.get_id());
         match stmt {
             Stmt::Block { statements: _ } => self.resolve_block(stmt)?,
             Stmt::Var {
                 name: _,
+                annotation: _,
                 initializer: _,
             } => self.resolve_var(stmt)?,
+            Stmt::Const { name, initializer } => {
+                self.declare(name)?;
+                self.resolve_expr(initializer)?;
+                self.define(name);
+                self.define_constant(name);
+            }
+            Stmt::Destructure {
+                keyword: _,
+                pattern,
+                initializer,
+            } => {
+                let names = pattern.bindings();
+                for name in names.iter() {
+                    self.declare(name)?;
+                }
+                self.resolve_expr(initializer)?;
+                for name in names {
+                    self.define(name);
+                }
+            }
             Stmt::Class {
                 name,
+                fields: _,
                 methods,
                 superclass,
+                traits,
             } => {
+                for trait_ in traits {
+                    self.resolve_expr(trait_)?;
+                }
+
                 // Resolve superclass, if present
                 if let Some(super_expr) = superclass {
                     if let Expr::Variable {
@@ -58,9 +107,10 @@ impl Resolver {
                         .insert("super".to_string(), true);
                 }
 
-                // Resolving class
+                // Resolving class, class names can not be reassigned
                 self.declare(name)?;
                 self.define(name);
+                self.define_constant(name);
 
                 // Resolving methods
                 self.begin_scope();
@@ -69,8 +119,7 @@ impl Resolver {
                     .unwrap()
                     .insert("this".to_string(), true);
                 for method in methods {
-                    let declaration = FunctionType::Method;
-                    self.resolve_function(method, declaration)?;
+                    self.resolve_method(method)?;
                 }
                 self.end_scope();
 
@@ -78,12 +127,88 @@ impl Resolver {
                     self.end_scope();
                 }
             }
+            Stmt::Enum { name, variants } => {
+                let mut seen = HashSet::new();
+                for variant in variants {
+                    if !seen.insert(&variant.name.lexeme) {
+                        return Err(format!(
+                            "Enum {} has more than one variant named {}",
+                            name.lexeme, variant.name.lexeme
+                        ));
+                    }
+                    let mut fields = HashSet::new();
+                    for field in variant.fields.iter().flatten() {
+                        if !fields.insert(&field.lexeme) {
+                            return Err(format!(
+                                "Variant {} has more than one field named {}",
+                                variant.name.lexeme, field.lexeme
+                            ));
+                        }
+                    }
+                }
+
+                // Like class names, enum names can not be reassigned
+                self.declare(name)?;
+                self.define(name);
+                self.define_constant(name);
+            }
+            Stmt::Trait { name, methods } => {
+                self.declare(name)?;
+                self.define(name);
+                self.define_constant(name);
+
+                // Trait methods have 'this' but no 'super'
+                self.begin_scope();
+                self.scopes
+                    .last_mut()
+                    .unwrap()
+                    .insert("this".to_string(), true);
+                for method in methods {
+                    self.resolve_method(method)?;
+                }
+                self.end_scope();
+            }
+            // Only found in class bodies, where resolve_method skips them
+            Stmt::AbstractMethod { .. } => (),
+            Stmt::Extend { class, methods } => {
+                self.resolve_expr(class)?;
+
+                // Same scopes as a class with a superclass, 'super' is checked at runtime
+                self.begin_scope();
+                self.scopes
+                    .last_mut()
+                    .unwrap()
+                    .insert("super".to_string(), true);
+                self.begin_scope();
+                self.scopes
+                    .last_mut()
+                    .unwrap()
+                    .insert("this".to_string(), true);
+                for method in methods {
+                    self.resolve_method(method)?;
+                }
+                self.end_scope();
+                self.end_scope();
+            }
             Stmt::Function {
                 name: _,
                 params: _,
+                return_type: _,
                 body: _,
-            } => self.resolve_function(stmt, FunctionType::Function)?,
-            Stmt::CmdFunction { name: _, cmd: _ } => self.resolve_var(stmt)?,
+                is_async,
+            } => {
+                let fn_type = if *is_async {
+                    FunctionType::AsyncFunction
+                } else {
+                    FunctionType::Function
+                };
+                self.resolve_function(stmt, fn_type)?
+            }
+            Stmt::CmdFunction {
+                name: _,
+                cmd: _,
+                is_async: _,
+            } => self.resolve_var(stmt)?,
             Stmt::Expression { expression } => self.resolve_expr(expression)?,
             Stmt::IfStmt {
                 predicate: _,
@@ -96,6 +221,36 @@ impl Resolver {
                     return Err("Return statement is not allowed outside of a function".to_string());
                 }
 
+                if let Some(value) = value {
+                    if self.current_function == FunctionType::Initializer {
+                        return Err("Cannot return a value from an initializer".to_string());
+                    }
+                    self.resolve_expr(value)?;
+                }
+            }
+            Stmt::Yield { keyword, value } => {
+                match self.current_function {
+                    FunctionType::None => {
+                        return Err(format!(
+                            "Line {}: Cannot yield outside of a function",
+                            keyword.line_number
+                        ))
+                    }
+                    FunctionType::Initializer => {
+                        return Err(format!(
+                            "Line {}: Cannot yield from an initializer",
+                            keyword.line_number
+                        ))
+                    }
+                    FunctionType::AsyncFunction => {
+                        return Err(format!(
+                            "Line {}: Cannot yield from an async function",
+                            keyword.line_number
+                        ))
+                    }
+                    _ => (),
+                }
+
                 if let Some(value) = value {
                     self.resolve_expr(value)?;
                 }
@@ -104,6 +259,20 @@ impl Resolver {
                 self.resolve_expr(condition)?;
                 self.resolve_internal(body.as_ref())?;
             }
+            Stmt::ForIn {
+                variable,
+                iterable,
+                body,
+            } => {
+                self.resolve_expr(iterable)?;
+
+                // The loop variable lives in its own scope, enclosing the body
+                self.begin_scope();
+                self.declare(variable)?;
+                self.define(variable);
+                self.resolve_internal(body.as_ref())?;
+                self.end_scope();
+            }
         }
         Ok(())
     }
@@ -116,9 +285,9 @@ impl Resolver {
         Ok(())
     }
 
-    pub fn resolve(mut self, stmts: &Vec<&Stmt>) -> Result<HashMap<usize, usize>, String> {
+    pub fn resolve(&mut self, stmts: &Vec<&Stmt>) -> Result<HashMap<usize, usize>, String> {
         self.resolve_many(stmts)?;
-        Ok(self.locals)
+        Ok(std::mem::take(&mut self.locals))
     }
 
     fn resolve_block(&mut self, stmt: &Stmt) -> Result<(), String> {
@@ -135,11 +304,16 @@ impl Resolver {
     }
 
     fn resolve_var(&mut self, stmt: &Stmt) -> Result<(), String> {
-        if let Stmt::Var { name, initializer } = stmt {
+        if let Stmt::Var {
+            name,
+            annotation: _,
+            initializer,
+        } = stmt
+        {
             self.declare(name)?;
             self.resolve_expr(initializer)?;
             self.define(name);
-        } else if let Stmt::CmdFunction {name, cmd: _} = stmt {
+        } else if let Stmt::CmdFunction { name, .. } = stmt {
             self.declare(name)?;
             self.define(name);
         } else {
@@ -150,7 +324,14 @@ impl Resolver {
     }
 
     fn resolve_function(&mut self, stmt: &Stmt, fn_type: FunctionType) -> Result<(), String> {
-        if let Stmt::Function { name, params, body } = stmt {
+        if let Stmt::Function {
+            name,
+            params,
+            return_type: _,
+            body,
+            is_async: _,
+        } = stmt
+        {
             self.declare(name)?;
             self.define(name);
 
@@ -164,6 +345,23 @@ impl Resolver {
         }
     }
 
+    fn resolve_method(&mut self, method: &Stmt) -> Result<(), String> {
+        match method {
+            Stmt::AbstractMethod { .. } => Ok(()),
+            Stmt::Function { name, .. } if name.lexeme == "init" => {
+                self.resolve_function(method, FunctionType::Initializer)
+            }
+            _ => self.resolve_function(method, FunctionType::Method),
+        }
+    }
+
+    fn in_method(&self) -> bool {
+        matches!(
+            self.current_function,
+            FunctionType::Method | FunctionType::Initializer
+        )
+    }
+
     fn resolve_if_stmt(&mut self, stmt: &Stmt) -> Result<(), String> {
         if let Stmt::IfStmt {
             predicate,
@@ -185,16 +383,27 @@ impl Resolver {
 
     fn resolve_function_helper(
         &mut self,
-        params: &Vec<Token>,
+        params: &Vec<Parameter>,
         body: &Vec<&Stmt>,
         resolving_function: FunctionType,
     ) -> Result<(), String> {
         let enclosing_function = self.current_function;
         self.current_function = resolving_function;
+        self.leading_await = None;
         self.begin_scope();
         for param in params {
-            self.declare(param)?;
-            self.define(param);
+            // Defaults are evaluated in the function scope, after the earlier parameters
+            if let Some(default) = &param.default {
+                self.resolve_expr(default)?;
+            }
+            self.declare(&param.name)?;
+            self.define(&param.name);
+            if let Some(pattern) = &param.pattern {
+                for name in pattern.bindings() {
+                    self.declare(name)?;
+                    self.define(name);
+                }
+            }
         }
         self.resolve_many(body)?;
         self.end_scope();
@@ -204,10 +413,19 @@ impl Resolver {
 
     fn begin_scope(&mut self) {
         self.scopes.push(HashMap::new());
+        self.constants.push(HashSet::new());
     }
 
     fn end_scope(&mut self) {
         self.scopes.pop().expect("Stack underflow");
+        self.constants.pop().expect("Stack underflow");
+    }
+
+    fn define_constant(&mut self, name: &Token) {
+        // Global constants are checked by the environment at runtime
+        if let Some(constants) = self.constants.last_mut() {
+            constants.insert(name.lexeme.clone());
+        }
     }
 
     fn declare(&mut self, name: &Token) -> Result<(), String> {
@@ -248,6 +466,37 @@ impl Resolver {
                 name: _,
                 value: _,
             } => self.resolve_expr_assign(expr, expr.get_id()),
+            Expr::AssignTuple {
+                id: _,
+                targets,
+                value,
+            } => {
+                self.resolve_expr(value)?;
+                self.resolve_tuple_targets(targets)
+            }
+            Expr::Await { id, keyword, value } => {
+                // The top level is not a task, an await there runs the event loop
+                if !matches!(
+                    self.current_function,
+                    FunctionType::None | FunctionType::AsyncFunction
+                ) {
+                    return Err(format!(
+                        "Line {}: Cannot await outside of an async function",
+                        keyword.line_number
+                    ));
+                }
+                // A task only suspends at an await that starts a statement, running
+                // the event loop anywhere else would keep other tasks from finishing
+                if self.current_function == FunctionType::AsyncFunction
+                    && self.leading_await != Some(*id)
+                {
+                    return Err(format!(
+                        "Line {}: An await in an async function must start a statement, like `var x = await task;`",
+                        keyword.line_number
+                    ));
+                }
+                self.resolve_expr(value)
+            }
             Expr::Binary {
                 id: _,
                 left,
@@ -262,14 +511,45 @@ impl Resolver {
                 callee,
                 paren: _,
                 arguments,
+                named_arguments,
             } => {
                 self.resolve_expr(callee.as_ref())?;
                 for arg in arguments {
                     self.resolve_expr(arg)?;
                 }
+                for (_, arg) in named_arguments {
+                    self.resolve_expr(arg)?;
+                }
+
+                Ok(())
+            }
+            Expr::Index {
+                id: _,
+                object,
+                bracket: _,
+                index,
+            } => {
+                self.resolve_expr(object)?;
+                self.resolve_expr(index)
+            }
+            Expr::List { id: _, elements } | Expr::Tuple { id: _, elements } => {
+                for element in elements {
+                    self.resolve_expr(element)?;
+                }
 
                 Ok(())
             }
+            Expr::SetIndex {
+                id: _,
+                object,
+                bracket: _,
+                index,
+                value,
+            } => {
+                self.resolve_expr(value)?;
+                self.resolve_expr(object)?;
+                self.resolve_expr(index)
+            }
             Expr::Get {
                 id: _,
                 object,
@@ -286,6 +566,47 @@ impl Resolver {
                 self.resolve_expr(left)?;
                 self.resolve_expr(right)
             }
+            Expr::Match {
+                id: _,
+                keyword,
+                subject,
+                arms,
+            } => {
+                self.resolve_expr(subject)?;
+                for arm in arms {
+                    if let Pattern::Alternatives(alternatives) = &arm.pattern {
+                        if alternatives.iter().any(|p| !p.bindings().is_empty()) {
+                            return Err(format!(
+                                "Line {}: Alternative patterns cannot bind names",
+                                keyword.line_number
+                            ));
+                        }
+                    }
+
+                    self.begin_scope();
+                    for name in arm.pattern.bindings() {
+                        self.declare(name)?;
+                        self.define(name);
+                    }
+                    if let Some(guard) = &arm.guard {
+                        self.resolve_expr(guard)?;
+                    }
+                    self.resolve_expr(&arm.body)?;
+                    self.end_scope();
+                }
+
+                let has_catch_all = arms
+                    .iter()
+                    .any(|arm| arm.guard.is_none() && arm.pattern.is_catch_all());
+                if !has_catch_all {
+                    self.warnings.push(format!(
+                        "Line {}: match has no catch-all arm ('_' or a binding without a guard)",
+                        keyword.line_number
+                    ));
+                }
+
+                Ok(())
+            }
             Expr::Set {
                 id: _,
                 object,
@@ -296,7 +617,7 @@ impl Resolver {
                 self.resolve_expr(object)
             }
             Expr::This { id: _, keyword } => {
-                if self.current_function != FunctionType::Method {
+                if !self.in_method() {
                     return Err("Cannot use 'this' keyword outside of a class".to_string());
                 }
                 self.resolve_local(keyword, expr.get_id())
@@ -306,7 +627,7 @@ impl Resolver {
                 keyword,
                 method: _,
             } => {
-                if self.current_function != FunctionType::Method {
+                if !self.in_method() {
                     return Err("Cannot use 'super' keyword outside of a class".to_string());
                 }
                 if self.scopes.len() < 3 || !self.scopes[self.scopes.len() - 3].contains_key("super") {
@@ -348,6 +669,7 @@ impl Resolver {
                 callee,
                 paren: _,
                 arguments: _,
+                named_arguments: _,
             } => match callee.as_ref() {
                 Expr::Variable { id: _, name } => self.resolve_local(&name, resolve_id),
                 _ => panic!("Wrong type in resolve_expr_var"),
@@ -377,11 +699,75 @@ impl Resolver {
     fn resolve_expr_assign(&mut self, expr: &Expr, resolve_id: usize) -> Result<(), String> {
         if let Expr::Assign { id: _, name, value } = expr {
             self.resolve_expr(value.as_ref())?;
-            self.resolve_local(name, resolve_id)?;
+            self.resolve_assign_target(name, resolve_id)?;
         } else {
             panic!("Wrong type in resolve assign");
         }
 
         Ok(())
     }
+
+    fn resolve_assign_target(&mut self, name: &Token, resolve_id: usize) -> Result<(), String> {
+        let scope = self
+            .scopes
+            .iter()
+            .rposition(|scope| scope.contains_key(&name.lexeme));
+        if let Some(i) = scope {
+            if self.constants[i].contains(&name.lexeme) {
+                return Err(format!(
+                    "Line {}: Cannot assign to constant '{}'",
+                    name.line_number, name.lexeme
+                ));
+            }
+        }
+
+        self.resolve_local(name, resolve_id)
+    }
+
+    // Every variable of `(a, (b, c)) = ...` is resolved like the target of an assignment
+    fn resolve_tuple_targets(&mut self, targets: &[Expr]) -> Result<(), String> {
+        for target in targets {
+            match target {
+                Expr::Variable { id, name } => self.resolve_assign_target(name, *id)?,
+                Expr::Tuple { id: _, elements } => self.resolve_tuple_targets(elements)?,
+                _ => panic!("Invalid tuple assignment target"),
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::Parser;
+    use crate::scanner::Scanner;
+
+    fn warnings_for(source: &str) -> Vec<String> {
+        let tokens = Scanner::new(source).scan_tokens().unwrap();
+        let stmts = Parser::new(tokens).parse().unwrap();
+        let mut resolver = Resolver::new();
+        resolver.resolve(&stmts.iter().collect()).unwrap();
+        resolver.warnings().to_vec()
+    }
+
+    #[test]
+    fn match_without_catch_all_warns() {
+        let warnings = warnings_for("var a = match 1 { 1 => 2, n if n > 1 => 3 };");
+        assert_eq!(warnings.len(), 1);
+        assert!(warnings[0].starts_with("Line 1: match has no catch-all arm"));
+
+        assert!(warnings_for("var a = match 1 { 1 => 2, _ => 3 };").is_empty());
+        assert!(warnings_for("var a = match 1 { 1 => 2, n => n };").is_empty());
+    }
+
+    #[test]
+    fn alternatives_cannot_bind() {
+        let tokens = Scanner::new("var a = match 1 { 1 | n => 2, _ => 3 };")
+            .scan_tokens()
+            .unwrap();
+        let stmts = Parser::new(tokens).parse().unwrap();
+        let result = Resolver::new().resolve(&stmts.iter().collect());
+        assert!(result.is_err());
+    }
 }
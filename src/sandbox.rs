@@ -0,0 +1,341 @@
+use crate::event_loop::EventLoop;
+use std::cell::{Cell, RefCell};
+use std::collections::HashSet;
+use std::rc::Rc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+// Calls deeper than this are a script error instead of a Rust stack overflow
+pub const DEFAULT_MAX_CALL_DEPTH: usize = 1000;
+
+// Stack a single call can use, with a margin over the most measured for a call
+// nested in statements and expressions. Frames are much larger in debug builds.
+const STACK_PER_CALL: usize = if cfg!(debug_assertions) {
+    256 * 1024
+} else {
+    32 * 1024
+};
+
+// Things a script can do outside of the interpreter. A sandboxed interpreter
+// only gets the ones it is given explicitly.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Capability {
+    Commands,
+    FileSystem,
+    Environment,
+    Process,
+    Threads,
+}
+
+impl Capability {
+    pub fn all() -> Vec<Capability> {
+        vec![
+            Capability::Commands,
+            Capability::FileSystem,
+            Capability::Environment,
+            Capability::Process,
+            Capability::Threads,
+        ]
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Capability::Commands => "commands",
+            Capability::FileSystem => "fs",
+            Capability::Environment => "env",
+            Capability::Process => "process",
+            Capability::Threads => "threads",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Config {
+    pub capabilities: HashSet<Capability>,
+    // Natives allowed even though their capability is not, e.g. "fs.exists"
+    pub allowed_natives: HashSet<String>,
+    pub max_steps: Option<usize>,
+    pub max_call_depth: Option<usize>,
+}
+
+impl Config {
+    // Everything allowed and no step limit, what the cii binary uses
+    pub fn unrestricted() -> Self {
+        Self {
+            capabilities: Capability::all().into_iter().collect(),
+            allowed_natives: HashSet::new(),
+            max_steps: None,
+            max_call_depth: Some(DEFAULT_MAX_CALL_DEPTH),
+        }
+    }
+
+    // Nothing that reaches outside the interpreter, with limits on how long
+    // and how deep a script may run
+    pub fn sandboxed() -> Self {
+        Self {
+            capabilities: HashSet::new(),
+            allowed_natives: HashSet::new(),
+            max_steps: Some(1_000_000),
+            max_call_depth: Some(100),
+        }
+    }
+
+    pub fn allows(&self, capability: Capability) -> bool {
+        self.capabilities.contains(&capability)
+    }
+
+    pub fn allows_native(&self, name: &str, capability: Capability) -> bool {
+        self.allows(capability) || self.allowed_natives.contains(name)
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self::unrestricted()
+    }
+}
+
+// Per interpreter state shared by every environment it creates
+#[derive(Debug)]
+pub struct Runtime {
+    pub config: Config,
+    // Shared with the threads the script starts, so they take from the same budget
+    steps: RefCell<Arc<AtomicUsize>>,
+    depth: Cell<usize>,
+    // Printed lines are collected here instead of going to stdout when set
+    output: RefCell<Option<String>>,
+    // Warnings about the program, kept instead of going to stderr while output is
+    // collected
+    warnings: RefCell<Vec<String>>,
+    // Runs the tasks started by async functions, spawn() and sleep()
+    pub event_loop: Rc<EventLoop>,
+    // Everything run so far, from which threads build their own interpreter
+    sources: RefCell<Vec<String>>,
+}
+
+impl Runtime {
+    pub fn new(config: Config) -> Self {
+        // sleep() must not let a script run longer than its step budget allows
+        let event_loop = match config.max_steps {
+            Some(_) => EventLoop::virtual_time(),
+            None => EventLoop::new(),
+        };
+        Self {
+            config,
+            steps: RefCell::new(Arc::new(AtomicUsize::new(0))),
+            depth: Cell::new(0),
+            output: RefCell::new(None),
+            warnings: RefCell::new(vec![]),
+            event_loop: Rc::new(event_loop),
+            sources: RefCell::new(vec![]),
+        }
+    }
+
+    pub fn require(&self, capability: Capability, what: &str) -> Result<(), String> {
+        if self.config.allows(capability) {
+            Ok(())
+        } else {
+            Err(denied_message(what, capability))
+        }
+    }
+
+    pub fn step(&self) -> Result<(), String> {
+        let steps = self.steps.borrow().fetch_add(1, Ordering::Relaxed) + 1;
+        match self.config.max_steps {
+            Some(max) if steps > max => Err(format!("Step budget of {} exceeded", max)),
+            _ => Ok(()),
+        }
+    }
+
+    pub fn step_counter(&self) -> Arc<AtomicUsize> {
+        self.steps.borrow().clone()
+    }
+
+    // Counts steps on the counter of another runtime, usually the one of the
+    // script that started this one on a thread
+    pub fn share_step_counter(&self, counter: Arc<AtomicUsize>) {
+        *self.steps.borrow_mut() = counter;
+    }
+
+    pub fn enter_call(&self, name: &str) -> Result<(), String> {
+        let depth = self.depth.get() + 1;
+        if let Some(max) = self.config.max_call_depth {
+            if depth > max {
+                return Err(format!(
+                    "Stack overflow: maximum call depth of {} exceeded when calling '{}'",
+                    max, name
+                ));
+            }
+        }
+        self.depth.set(depth);
+        Ok(())
+    }
+
+    pub fn exit_call(&self) {
+        self.depth.set(self.depth.get() - 1);
+    }
+
+    pub fn print(&self, line: &str) {
+        match self.output.borrow_mut().as_mut() {
+            Some(buffer) => {
+                buffer.push_str(line);
+                buffer.push('\n');
+            }
+            None => println!("{}", line),
+        }
+    }
+
+    pub fn warn(&self, warning: &str) {
+        if self.is_capturing_output() {
+            self.warnings.borrow_mut().push(warning.to_string());
+        } else {
+            eprintln!("Warning: {}", warning);
+        }
+    }
+
+    pub fn take_warnings(&self) -> Vec<String> {
+        std::mem::take(&mut self.warnings.borrow_mut())
+    }
+
+    pub fn capture_output(&self) {
+        *self.output.borrow_mut() = Some(String::new());
+    }
+
+    pub fn is_capturing_output(&self) -> bool {
+        self.output.borrow().is_some()
+    }
+
+    pub fn add_source(&self, source: &str) {
+        self.sources.borrow_mut().push(source.to_string());
+    }
+
+    pub fn sources(&self) -> Vec<String> {
+        self.sources.borrow().clone()
+    }
+
+    pub fn take_output(&self) -> String {
+        match self.output.borrow_mut().as_mut() {
+            Some(buffer) => std::mem::take(buffer),
+            None => String::new(),
+        }
+    }
+}
+
+// Stack for a thread running scripts with the config, enough to reach its call depth
+// limit before the thread's stack overflows. The stack is only reserved up front,
+// pages are used as it grows.
+pub fn stack_size(config: &Config) -> usize {
+    let depth = config.max_call_depth.unwrap_or(DEFAULT_MAX_CALL_DEPTH);
+    (depth + 1) * STACK_PER_CALL
+}
+
+pub fn denied_message(what: &str, capability: Capability) -> String {
+    format!(
+        "{} is not allowed in this sandbox (needs the '{}' capability)",
+        what,
+        capability.name()
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::interpreter::Interpreter;
+    use crate::parser::Parser;
+    use crate::resolver::Resolver;
+    use crate::scanner::Scanner;
+
+    fn run(config: Config, source: &str) -> Result<(), String> {
+        let tokens = Scanner::new(source).scan_tokens()?;
+        let stmts = Parser::new(tokens).parse()?;
+        let locals = Resolver::new().resolve(&stmts.iter().collect())?;
+
+        let mut interpreter = Interpreter::with_config(config);
+        interpreter.resolve(locals);
+        interpreter.interpret(stmts.iter().collect())
+    }
+
+    #[test]
+    fn command_functions_need_capability() {
+        let source = "fun ls <- \"ls\";";
+        assert_eq!(
+            run(Config::sandboxed(), source).unwrap_err(),
+            "Command function 'ls' is not allowed in this sandbox (needs the 'commands' capability)"
+        );
+
+        let mut config = Config::sandboxed();
+        config.capabilities.insert(Capability::Commands);
+        assert!(run(config, source).is_ok());
+    }
+
+    #[test]
+    fn os_natives_need_capability() {
+        assert_eq!(
+            run(Config::sandboxed(), "fs.read_file(\"/etc/hostname\");").unwrap_err(),
+            "fs.read_file is not allowed in this sandbox (needs the 'fs' capability)"
+        );
+        assert!(run(Config::sandboxed(), "getenv(\"HOME\");").is_err());
+        assert!(run(Config::sandboxed(), "exit(0);").is_err());
+        assert!(run(Config::sandboxed(), "fun f() {} thread_spawn(f);").is_err());
+
+        // Pure natives stay available
+        assert!(run(Config::sandboxed(), "print path.join(\"a\", \"b\"); print len(\"ab\");").is_ok());
+
+        let mut config = Config::sandboxed();
+        config.allowed_natives.insert("fs.exists".to_string());
+        assert!(run(config.clone(), "print fs.exists(\"/\");").is_ok());
+        assert!(run(config, "fs.remove(\"/tmp/x\");").is_err());
+    }
+
+    #[test]
+    fn warnings_are_captured_with_output() {
+        let interpreter = &mut Interpreter::new();
+        interpreter.capture_output();
+        let source = "print match 2 { 1 => \"one\", n if n > 1 => \"more\" };";
+        crate::run(interpreter, source).unwrap();
+
+        assert_eq!(interpreter.take_output(), "\"more\"\n");
+        let warnings = interpreter.environment.runtime.take_warnings();
+        assert_eq!(warnings.len(), 1);
+        assert!(warnings[0].starts_with("Line 1: match has no catch-all arm"));
+    }
+
+    #[test]
+    fn step_budget() {
+        let mut config = Config::sandboxed();
+        config.max_steps = Some(100);
+        assert_eq!(
+            run(config, "while (true) { }").unwrap_err(),
+            "Step budget of 100 exceeded"
+        );
+    }
+
+    #[test]
+    fn sleep_does_not_outlast_step_budget() {
+        let started = std::time::Instant::now();
+        let source = "async fun nap() { await sleep(1000000000); return 1; } print await nap();";
+        assert!(run(Config::sandboxed(), source).is_ok());
+        assert!(started.elapsed() < std::time::Duration::from_secs(5));
+    }
+
+    #[test]
+    fn call_depth_limit() {
+        let mut config = Config::sandboxed();
+        config.max_call_depth = Some(10);
+        let source = "fun down(n) { if (n == 0) return 0; return down(n - 1); }";
+
+        assert!(run(config.clone(), &format!("{} down(8);", source)).is_ok());
+        assert_eq!(
+            run(config, &format!("{} down(20);", source)).unwrap_err(),
+            "Stack overflow: maximum call depth of 10 exceeded when calling 'down'"
+        );
+    }
+
+    #[test]
+    fn stack_size_follows_call_depth() {
+        let mut config = Config::sandboxed();
+        config.max_call_depth = Some(9);
+        assert_eq!(stack_size(&config), 10 * STACK_PER_CALL);
+        assert!(stack_size(&Config::sandboxed()) < stack_size(&Config::unrestricted()));
+    }
+}
@@ -17,21 +17,32 @@ fn is_alpha_numeric(ch: char) -> bool {
 fn get_keywords_hashmap() -> HashMap<&'static str, TokenType> {
     HashMap::from([
         ("and", And),
+        ("async", Async),
+        ("await", Await),
         ("class", Class),
+        ("const", Const),
         ("else", Else),
+        ("enum", Enum),
+        ("extend", Extend),
         ("false", False),
         ("for", For),
         ("fun", Fun),
         ("if", If),
+        ("in", In),
+        ("is", Is),
+        ("match", Match),
         ("nil", Nil),
         ("or", Or),
         ("print", Print),
         ("return", Return),
         ("super", Super),
         ("this", This),
+        ("trait", Trait),
         ("true", True),
         ("var", Var),
         ("while", While),
+        ("with", With),
+        ("yield", Yield),
     ])
 }
 
@@ -41,6 +52,10 @@ pub struct Scanner {
     start: usize,
     current: usize,
     line: usize,
+    // Index of the first character on the current line, for columns
+    line_start: usize,
+    start_line: usize,
+    start_column: usize,
 
     keywords: HashMap<&'static str, TokenType>,
 }
@@ -53,6 +68,9 @@ impl Scanner {
             start: 0,
             current: 0,
             line: 1,
+            line_start: 0,
+            start_line: 1,
+            start_column: 1,
             keywords: get_keywords_hashmap(),
         }
     }
@@ -61,6 +79,8 @@ impl Scanner {
         let mut errors = vec![];
         while !self.is_at_end() {
             self.start = self.current;
+            self.start_line = self.line;
+            self.start_column = self.current - self.line_start + 1;
             match self.scan_token() {
                 Ok(_) => (),
                 Err(msg) => errors.push(msg),
@@ -72,6 +92,7 @@ impl Scanner {
             lexeme: "".to_string(),
             literal: None,
             line_number: self.line,
+            column: self.current - self.line_start + 1,
         });
 
         if errors.len() > 0 {
@@ -100,9 +121,33 @@ impl Scanner {
             ')' => self.add_token(RightParen),
             '{' => self.add_token(LeftBrace),
             '}' => self.add_token(RightBrace),
+            '[' => self.add_token(LeftBracket),
+            ']' => self.add_token(RightBracket),
             ',' => self.add_token(Comma),
-            '.' => self.add_token(Dot),
-            '-' => self.add_token(Minus),
+            '.' => {
+                let token = if self.char_match('.') {
+                    if self.char_match('=') {
+                        DotDotEqual
+                    } else if self.char_match('.') {
+                        DotDotDot
+                    } else {
+                        DotDot
+                    }
+                } else {
+                    Dot
+                };
+
+                self.add_token(token);
+            }
+            '-' => {
+                let token = if self.char_match('>') {
+                    Arrow
+                } else {
+                    Minus
+                };
+
+                self.add_token(token);
+            }
             '+' => self.add_token(Plus),
             ';' => self.add_token(Semicolon),
             '*' => self.add_token(Star),
@@ -118,6 +163,8 @@ impl Scanner {
             '=' => {
                 let token = if self.char_match('=') {
                     EqualEqual
+                } else if self.char_match('>') {
+                    FatArrow
                 } else {
                     Equal
                 };
@@ -157,14 +204,17 @@ impl Scanner {
                 }
             },
             '|' => {
-                if self.char_match('>') {
-                    self.add_token(Pipe);
+                let token = if self.char_match('>') {
+                    Pipe
                 } else {
-                    return Err(format!("Expected '>' at line {}", self.line));
-                }
+                    Bar
+                };
+
+                self.add_token(token);
             },
+            ':' => self.add_token(Colon),
             ' ' | '\r' | '\t' => {}
-            '\n' => self.line += 1,
+            '\n' => self.new_line(),
             '"' => self.string()?,
 
             c => {
@@ -173,7 +223,10 @@ impl Scanner {
                 } else if is_alpha(c) {
                     self.identifier();
                 } else {
-                    return Err(format!("Unrecognized char at line {}: {}", self.line, c));
+                    return Err(format!(
+                        "Unrecognized char at line {}, column {}: {}",
+                        self.start_line, self.start_column, c
+                    ));
                 }
             }
         }
@@ -226,14 +279,17 @@ impl Scanner {
 
     fn string(self: &mut Self) -> Result<(), String> {
         while self.peek() != '"' && !self.is_at_end() {
-            if self.peek() == '\n' {
-                self.line += 1;
+            let c = self.advance();
+            if c == '\n' {
+                self.new_line();
             }
-            self.advance();
         }
 
         if self.is_at_end() {
-            return Err("Unterminated string".to_string());
+            return Err(format!(
+                "Unterminated string starting at line {}, column {}",
+                self.start_line, self.start_column
+            ));
         }
 
         self.advance();
@@ -271,6 +327,12 @@ impl Scanner {
         c
     }
 
+    // Called after consuming a '\n'
+    fn new_line(&mut self) {
+        self.line += 1;
+        self.line_start = self.current;
+    }
+
     fn add_token(self: &mut Self, token_type: TokenType) {
         self.add_token_lit(token_type, None);
     }
@@ -282,7 +344,8 @@ impl Scanner {
             token_type: token_type,
             lexeme: text,
             literal: literal,
-            line_number: self.line,
+            line_number: self.start_line,
+            column: self.start_column,
         });
     }
 }
@@ -294,6 +357,8 @@ pub enum TokenType {
     RightParen,
     LeftBrace,
     RightBrace,
+    LeftBracket,
+    RightBracket,
     Comma,
     Dot,
     Minus,
@@ -301,6 +366,8 @@ pub enum TokenType {
     Semicolon,
     Slash,
     Star,
+    Colon,
+    Bar,
 
     // One Or Two Chars
     Bang,
@@ -313,6 +380,11 @@ pub enum TokenType {
     LessEqual,
     Pipe, // |>
     Gets, // <-
+    Arrow, // ->
+    DotDot, // ..
+    DotDotEqual, // ..=
+    DotDotDot, // ...
+    FatArrow, // =>
 
     // Literals
     Identifier,
@@ -321,21 +393,32 @@ pub enum TokenType {
 
     // Keywords
     And,
+    Async,
+    Await,
     Class,
+    Const,
     Else,
+    Enum,
+    Extend,
     False,
     Fun,
     For,
     If,
+    In,
+    Is,
+    Match,
     Nil,
     Or,
     Print,
     Return,
     Super,
     This,
+    Trait,
     True,
     Var,
     While,
+    With,
+    Yield,
 
     Eof,
 }
@@ -360,6 +443,7 @@ pub struct Token {
     pub lexeme: String,
     pub literal: Option<LiteralValue>,
     pub line_number: usize,
+    pub column: usize,
 }
 
 impl Token {
@@ -516,4 +600,73 @@ mod tests {
         assert_eq!(scanner.tokens[4].token_type, Semicolon);
         assert_eq!(scanner.tokens[5].token_type, Eof);
     }
+
+    #[test]
+    fn arrow_token() {
+        let source = "a -> a - 1";
+        let mut scanner = Scanner::new(source);
+        scanner.scan_tokens().unwrap();
+
+        assert_eq!(scanner.tokens.len(), 6);
+
+        assert_eq!(scanner.tokens[0].token_type, Identifier);
+        assert_eq!(scanner.tokens[1].token_type, Arrow);
+        assert_eq!(scanner.tokens[2].token_type, Identifier);
+        assert_eq!(scanner.tokens[3].token_type, Minus);
+        assert_eq!(scanner.tokens[4].token_type, Number);
+        assert_eq!(scanner.tokens[5].token_type, Eof);
+    }
+
+    #[test]
+    fn match_tokens() {
+        let source = "match x { 1 | 2 => a, P { y: _ } => b }";
+        let mut scanner = Scanner::new(source);
+        scanner.scan_tokens().unwrap();
+
+        let types: Vec<TokenType> = scanner.tokens.iter().map(|t| t.token_type).collect();
+        assert_eq!(
+            types,
+            vec![
+                Match, Identifier, LeftBrace, Number, Bar, Number, FatArrow, Identifier, Comma,
+                Identifier, LeftBrace, Identifier, Colon, Identifier, RightBrace, FatArrow,
+                Identifier, RightBrace, Eof
+            ]
+        );
+    }
+
+    #[test]
+    fn range_tokens() {
+        let source = "0..10 1.5..=3 a.b";
+        let mut scanner = Scanner::new(source);
+        scanner.scan_tokens().unwrap();
+
+        assert_eq!(scanner.tokens.len(), 10);
+
+        assert_eq!(scanner.tokens[0].token_type, Number);
+        assert_eq!(scanner.tokens[1].token_type, DotDot);
+        assert_eq!(scanner.tokens[2].token_type, Number);
+        assert_eq!(scanner.tokens[3].token_type, Number);
+        assert_eq!(scanner.tokens[4].token_type, DotDotEqual);
+        assert_eq!(scanner.tokens[5].token_type, Number);
+        assert_eq!(scanner.tokens[6].token_type, Identifier);
+        assert_eq!(scanner.tokens[7].token_type, Dot);
+        assert_eq!(scanner.tokens[8].token_type, Identifier);
+        assert_eq!(scanner.tokens[9].token_type, Eof);
+    }
+
+    #[test]
+    fn rest_and_list_tokens() {
+        let source = "(a, ...rest) [1]";
+        let mut scanner = Scanner::new(source);
+        scanner.scan_tokens().unwrap();
+
+        let types: Vec<TokenType> = scanner.tokens.iter().map(|t| t.token_type).collect();
+        assert_eq!(
+            types,
+            vec![
+                LeftParen, Identifier, Comma, DotDotDot, Identifier, RightParen, LeftBracket,
+                Number, RightBracket, Eof
+            ]
+        );
+    }
 }
@@ -1,6 +1,20 @@
-use crate::expr::Expr;
+use crate::expr::{Expr, Parameter, Pattern};
 use crate::scanner::Token;
 
+// `Circle(radius)` has fields, `Empty` has none and is a value by itself
+#[derive(Debug, Clone)]
+pub struct EnumVariant {
+    pub name: Token,
+    pub fields: Option<Vec<Token>>,
+}
+
+// `name: String;` in a class body, only used by the type checker
+#[derive(Debug, Clone)]
+pub struct Field {
+    pub name: Token,
+    pub annotation: Token,
+}
+
 #[derive(Debug, Clone)]
 pub enum Stmt {
     Expression {
@@ -11,6 +25,18 @@ pub enum Stmt {
     },
     Var {
         name: Token,
+        // var x: Number
+        annotation: Option<Token>,
+        initializer: Expr,
+    },
+    Const {
+        name: Token,
+        initializer: Expr,
+    },
+    // var (q, r) = divmod(7, 2);
+    Destructure {
+        keyword: Token,
+        pattern: Pattern,
         initializer: Expr,
     },
     Block {
@@ -18,8 +44,25 @@ pub enum Stmt {
     },
     Class {
         name: Token,
+        fields: Vec<Field>,
         methods: Vec<Box<Stmt>>,
         superclass: Option<Expr>,
+        // Traits mixed in with `with A, B`
+        traits: Vec<Expr>,
+    },
+    Enum {
+        name: Token,
+        variants: Vec<EnumVariant>,
+    },
+    // `trait Name { methods }`, where methods without a body are required
+    Trait {
+        name: Token,
+        methods: Vec<Box<Stmt>>,
+    },
+    // `extend Name { methods }` adds methods to an existing class
+    Extend {
+        class: Expr,
+        methods: Vec<Box<Stmt>>,
     },
     IfStmt {
         predicate: Expr,
@@ -30,19 +73,40 @@ pub enum Stmt {
         condition: Expr,
         body: Box<Stmt>,
     },
+    ForIn {
+        variable: Token,
+        iterable: Expr,
+        body: Box<Stmt>,
+    },
     Function {
         name: Token,
-        params: Vec<Token>,
+        params: Vec<Parameter>,
+        // fun f() -> Number
+        return_type: Option<Token>,
         body: Vec<Box<Stmt>>,
+        // async fun, calling it starts a task
+        is_async: bool,
+    },
+    // A method declared as `name(params);`, to be implemented by subclasses
+    AbstractMethod {
+        name: Token,
+        params: Vec<Parameter>,
     },
     CmdFunction {
         name: Token,
         cmd: String,
+        // async fun name <- "cmd"; runs the command next to other tasks
+        is_async: bool,
     },
     ReturnStmt {
         keyword: Token,
         value: Option<Expr>,
     },
+    // Suspends a generator, handing the value to whoever called next()
+    Yield {
+        keyword: Token,
+        value: Option<Expr>,
+    },
 }
 
 impl Stmt {
@@ -54,8 +118,26 @@ impl Stmt {
             Print { expression } => format!("(print {})", expression.to_string()),
             Var {
                 name,
+                annotation: _,
                 initializer: _,
             } => format!("(var {})", name.lexeme),
+            Const {
+                name,
+                initializer: _,
+            } => format!("(const {})", name.lexeme),
+            Destructure {
+                keyword: _,
+                pattern,
+                initializer: _,
+            } => format!(
+                "(var {})",
+                pattern
+                    .bindings()
+                    .iter()
+                    .map(|name| name.lexeme.clone())
+                    .collect::<Vec<String>>()
+                    .join(" ")
+            ),
             Block { statements } => format!(
                 "(block {})",
                 statements
@@ -75,9 +157,15 @@ impl Stmt {
             Function {
                 name: _,
                 params: _,
+                return_type: _,
                 body: _,
+                is_async: _,
+            } => todo!(),
+            CmdFunction {
+                name: _,
+                cmd: _,
+                is_async: _,
             } => todo!(),
-            CmdFunction { name: _, cmd: _ } => todo!(),
             ReturnStmt { keyword: _, value: _ } => todo!(),
             _ => todo!(),
         }
@@ -1,111 +1,267 @@
+// Line diff of expected against actual, `-` lines are missing and `+` lines unexpected
 #[cfg(test)]
-mod tests {
-    use std::fs::{read_dir, read_to_string, DirEntry};
-    use std::process::Command;
-
-    #[test]
-    fn execute_tests() {
-        let cases = read_dir("/home/codescope/projects/cii/src/tests/cases").unwrap();
-
-        let mut errors = vec![];
-        let mut msgs = vec![];
-        for case in cases {
-            let case = case.unwrap();
-            let name = case.path().display().to_string();
-            if name.contains("~") {
-                continue;
-            }
-
-            match run_test(case) {
-                Ok(_) => {
-                    msgs.push(format!("Running {name:.<85}...ok"));
-                },
-                Err(msg) => {
-                    errors.push(msg);
-                    msgs.push(format!("Running {name:.<85}...failed"));
-                }
-            }
+fn diff(expected: &[String], actual: &[String]) -> String {
+    // Longest common subsequence table
+    let mut lcs = vec![vec![0; actual.len() + 1]; expected.len() + 1];
+    for i in (0..expected.len()).rev() {
+        for j in (0..actual.len()).rev() {
+            lcs[i][j] = if expected[i] == actual[j] {
+                lcs[i + 1][j + 1] + 1
+            } else {
+                lcs[i + 1][j].max(lcs[i][j + 1])
+            };
         }
+    }
 
-        println!("Ran {} tests", msgs.len());
-        msgs.sort();
-        for msg in msgs {
-            println!("{}", msg);
+    let mut lines = vec![];
+    let (mut i, mut j) = (0, 0);
+    while i < expected.len() || j < actual.len() {
+        if i < expected.len() && j < actual.len() && expected[i] == actual[j] {
+            lines.push(format!("  {}", expected[i]));
+            i += 1;
+            j += 1;
+        } else if j < actual.len() && (i == expected.len() || lcs[i][j + 1] >= lcs[i + 1][j]) {
+            lines.push(format!("+ {}", actual[j]));
+            j += 1;
+        } else {
+            lines.push(format!("- {}", expected[i]));
+            i += 1;
         }
+    }
 
-        if errors.len() > 0 {
-            panic!("Errors:\n\n{}", errors.join("\n\n"));
-        }
+    lines.join("\n")
+}
+
+#[cfg(test)]
+mod cases {
+    use crate::interpreter::Interpreter;
+    use crate::natives::process::take_exit_status;
+    use crate::run;
+    use crate::sandbox::{stack_size, Config};
+    use std::fs::read_to_string;
+    use std::path::Path;
+    use std::thread;
+
+    use super::diff;
+
+    // One test per case file, see build.rs
+    include!(concat!(env!("OUT_DIR"), "/cases.rs"));
+
+    // A case file has a `// --- Test` section with the program, a `// --- Expected`
+    // section with its output and, for programs that fail, an `// --- Expected error`
+    // section with the error message. Programs that call `exit` have an
+    // `// --- Expected exit` section with the status instead of an error. Expected
+    // lines are written as `// line`, an error line ending in `...` only has to
+    // match the start of the message, for the parts that depend on the OS.
+    struct Case {
+        code: String,
+        output: Vec<String>,
+        error: Vec<String>,
+        exit: Option<i32>,
     }
 
-    fn run_test(file: DirEntry) -> Result<(), String> {
-        // Parse input and expected
-        let contents = read_to_string(file.path()).unwrap();
-        let lines = contents.split("\n").collect::<Vec<&str>>();
+    enum Section {
+        Test,
+        Expected,
+        ExpectedError,
+        ExpectedExit,
+    }
 
-        let mut test_code = vec![];
+    fn parse_case(name: &str, contents: &str) -> Case {
+        let mut section = None;
+        let mut code = vec![];
+        let mut output = vec![];
+        let mut error = vec![];
+        let mut exit = None;
 
-        let mut idx = None;
-        for (i, line) in lines.iter().enumerate() {
+        for line in contents.lines() {
             if line.starts_with("// --- Test") {
+                section = Some(Section::Test);
+                continue;
+            }
+            if line.starts_with("// --- Expected error") {
+                section = Some(Section::ExpectedError);
+                continue;
+            }
+            if line.starts_with("// --- Expected exit") {
+                section = Some(Section::ExpectedExit);
                 continue;
             }
             if line.starts_with("// --- Expected") {
-                idx = Some(i);
-                break;
+                section = Some(Section::Expected);
+                continue;
+            }
+
+            match section {
+                Some(Section::Test) => code.push(line),
+                Some(Section::Expected) => output.extend(expected_line(line)),
+                Some(Section::ExpectedError) => error.extend(expected_line(line)),
+                Some(Section::ExpectedExit) => {
+                    if let Some(status) = expected_line(line) {
+                        let status = status.parse().unwrap_or_else(|_| {
+                            panic!("{}: exit status '{}' is not a number", name, status)
+                        });
+                        exit = Some(status);
+                    }
+                }
+                None => panic!("{}: content before the `// --- Test` section", name),
             }
-            test_code.push(line.clone());
         }
 
-        let idx = idx.expect(&format!(
-            "{:#?}: No expected section in test case definition",
-            file.file_name()
-        ));
+        Case {
+            code: code.join("\n"),
+            output: trim_trailing_blank(output),
+            error: trim_trailing_blank(error),
+            exit,
+        }
+    }
 
-        let mut expected_output = vec![];
+    fn trim_trailing_blank(mut lines: Vec<String>) -> Vec<String> {
+        while lines.last().is_some_and(|line| line.is_empty()) {
+            lines.pop();
+        }
+        lines
+    }
 
-        for line in &lines[idx + 1..] {
-            if line.len() > 0 {
-                let string = line.to_string();
-                expected_output.push((string[3..]).to_string());
-            }
+    fn expected_line(line: &str) -> Option<String> {
+        if line.trim().is_empty() {
+            return None;
         }
+        let text = line.strip_prefix("//").unwrap_or(line);
+        let text = text.strip_prefix(' ').unwrap_or(text);
+        Some(text.trim_end().to_string())
+    }
 
-        let input = test_code.join("\n");
+    fn run_case(name: &str) {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("src/tests/cases")
+            .join(name);
+        let contents = read_to_string(&path)
+            .unwrap_or_else(|e| panic!("Could not read {}: {}", path.display(), e));
+        let case = parse_case(name, &contents);
 
-        let output = Command::new("cargo")
-            .arg("run")
-            .arg("e")
-            .arg(input)
-            .output()
-            .unwrap();
-        let lines = std::str::from_utf8(output.stdout.as_slice())
+        // Same stack as the cii binary, so deep recursion behaves the same way
+        let code = case.code.clone();
+        let (output, result, exit) = thread::Builder::new()
+            .stack_size(stack_size(&Config::default()))
+            .spawn(move || {
+                let mut interpreter = Interpreter::with_config(Config::default());
+                interpreter.capture_output();
+                let result = run(&mut interpreter, &code);
+                (interpreter.take_output(), result, take_exit_status())
+            })
             .unwrap()
-            .split("\n")
-            .collect::<Vec<&str>>();
-
-        if !(lines.len() == expected_output.len() || lines.len() == expected_output.len() + 1) {
-            return Err(format!(
-                "{:#?}: output length does not match expected output: {} != {}\nFull output:\n{}",
-                file.file_name(),
-                lines.len(),
-                expected_output.len(),
-                lines.join("\n")
+            .join()
+            .unwrap();
+
+        let output = trim_trailing_blank(output.lines().map(|l| l.trim_end().to_string()).collect());
+        // The error raised by `exit` only unwinds the script, the status is checked instead
+        let error = match &result {
+            Ok(_) => vec![],
+            Err(_) if exit.is_some() => vec![],
+            Err(msg) => trim_trailing_blank(msg.lines().map(|l| l.trim_end().to_string()).collect()),
+        };
+
+        let mut failures = vec![];
+        if output != case.output {
+            failures.push(format!("Output differs:\n{}", diff(&case.output, &output)));
+        }
+        if !error_matches(&case.error, &error) {
+            failures.push(format!("Error differs:\n{}", diff(&case.error, &error)));
+        }
+        if exit != case.exit {
+            failures.push(format!(
+                "Exit status differs: expected {:?} but got {:?}",
+                case.exit, exit
             ));
         }
+        if !failures.is_empty() {
+            panic!("{}:\n{}", name, failures.join("\n"));
+        }
+    }
+
+    fn error_matches(expected: &[String], actual: &[String]) -> bool {
+        expected.len() == actual.len()
+            && expected.iter().zip(actual).all(|(expected, actual)| {
+                match expected.strip_suffix("...") {
+                    Some(prefix) => actual.starts_with(prefix),
+                    None => expected == actual,
+                }
+            })
+    }
+
+    #[test]
+    fn error_lines_can_match_a_prefix() {
+        let actual = vec!["could not read 'x': gone (os error 2)".to_string()];
+        assert!(error_matches(
+            &["could not read 'x': ...".to_string()],
+            &actual
+        ));
+        assert!(!error_matches(
+            &["could not read 'y'...".to_string()],
+            &actual
+        ));
+        assert!(!error_matches(&["could not read".to_string()], &actual));
+    }
 
-        for (i, expected) in expected_output.iter().enumerate() {
-            if lines[i] != (*expected).trim() {
-                return Err(format!(
-                    "{:#?}: {} != {}\nFull output:\n{}",
-                    file.file_name(),
-                    lines[i],
-                    expected,
-                    lines.join("\n")
-                ));
+    #[test]
+    fn diff_marks_changed_lines() {
+        let expected = vec!["a".to_string(), "b".to_string(), "c".to_string()];
+        let actual = vec!["a".to_string(), "x".to_string(), "c".to_string()];
+        assert_eq!(diff(&expected, &actual), "  a\n+ x\n- b\n  c");
+    }
+}
+
+// Golden files for the token and tree dumps. Each `name.jlox` in src/tests/snapshots
+// has a `name.tokens` and `name.ast` next to it. Run the tests with
+// UPDATE_SNAPSHOTS=1 to write them after an intended change, and review the diff.
+#[cfg(test)]
+mod snapshots {
+    use crate::{dump_ast, dump_tokens};
+    use std::env;
+    use std::fs::{read_to_string, write};
+    use std::path::Path;
+
+    use super::diff;
+
+    // One test per snapshot input, see build.rs
+    include!(concat!(env!("OUT_DIR"), "/snapshots.rs"));
+
+    fn check_snapshot(name: &str) {
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("src/tests/snapshots");
+        let source = read_to_string(dir.join(name)).unwrap();
+        let update = env::var("UPDATE_SNAPSHOTS").is_ok();
+
+        let mut failures = vec![];
+        for (extension, dump) in [("tokens", dump_tokens(&source)), ("ast", dump_ast(&source))] {
+            let actual = dump.unwrap_or_else(|e| panic!("{}: {}", name, e));
+            let golden = dir.join(name).with_extension(extension);
+
+            if update {
+                write(&golden, &actual).unwrap();
+                continue;
+            }
+
+            match read_to_string(&golden) {
+                Ok(expected) if expected == actual => (),
+                Ok(expected) => {
+                    let expected: Vec<String> = expected.lines().map(String::from).collect();
+                    let actual: Vec<String> = actual.lines().map(String::from).collect();
+                    failures.push(format!(
+                        "{} differs:\n{}",
+                        golden.display(),
+                        diff(&expected, &actual)
+                    ));
+                }
+                Err(_) => failures.push(format!(
+                    "{} is missing, run with UPDATE_SNAPSHOTS=1 to create it",
+                    golden.display()
+                )),
             }
         }
 
-        Ok(())
+        if !failures.is_empty() {
+            panic!("{}:\n{}", name, failures.join("\n"));
+        }
     }
 }